@@ -0,0 +1,415 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::extract::State;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::header::AUTHORIZATION;
+use hyper::{HeaderMap, StatusCode};
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use sqlx_core::connection::Connection;
+use sqlx_core::executor::Executor;
+use sqlx_core::postgres::{PgConnection, Postgres};
+use sqlx_core::query_scalar::query_scalar;
+use sqlx_core::transaction::Transaction;
+
+use crate::api_key::API_KEY_HEADER;
+use crate::config::AuthConfig;
+use crate::rate_limit;
+use crate::{AppState, MyError, Result};
+
+pub type Claims = serde_json::Map<String, serde_json::Value>;
+
+/// Publishes the claims and request to postgres for the rest of the transaction, so row level
+/// security policies can use `current_setting('request.jwt.claims', true)::json->>'sub'`.
+/// Sets the `statement_timeout` when given and returns the pid of the backend.
+const SET_REQUEST_CONTEXT: &str = "SELECT pg_backend_pid(), \
+    set_config('request.jwt.claims', $1, true), \
+    set_config('request.method', $2, true), \
+    set_config('request.path', $3, true), \
+    set_config('request.headers', $4, true), \
+    set_config('statement_timeout', coalesce($5, current_setting('statement_timeout')), true)";
+
+/// Who a request runs as, added to the request extensions by `authenticate`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Identity {
+    /// Switched to with `SET LOCAL ROLE`, `None` keeps the login role of the pool
+    pub role: Option<String>,
+    /// Verified claims of the token, empty for anonymous requests
+    pub claims: Claims,
+    /// Tables an api key is limited to, `None` allows every table
+    pub tables: Option<HashSet<String>>,
+    pub request: RequestContext,
+}
+
+impl Identity {
+    /// Answers 403 when the table is not in the allowlist of the api key.
+    pub fn check_table(&self, table_name: &str) -> Result<()> {
+        match &self.tables {
+            Some(tables) if !tables.contains(table_name) => Err(MyError::from(anyhow::anyhow!(
+                "table {table_name} is not allowed for this api key"
+            ))
+            .with_status(StatusCode::FORBIDDEN)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Key the rate limits of the verified token subject or api key are kept under, `None`
+    /// for anonymous requests, which are limited by IP address.
+    pub fn rate_limit_key(&self) -> Option<String> {
+        if let Some(subject) = self.claims.get("sub").and_then(|sub| sub.as_str()) {
+            return Some(format!("sub:{subject}"));
+        }
+        let name = self.claims.get("api_key")?.as_str()?;
+        Some(format!("api_key:{name}"))
+    }
+}
+
+/// The parts of the request that are published to postgres.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestContext {
+    pub method: String,
+    pub path: String,
+    /// The configured headers that were sent, keyed by lowercase name
+    pub headers: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RequestContext {
+    pub fn new<B>(request: &Request<B>, header_names: &[String]) -> RequestContext {
+        let headers = header_names
+            .iter()
+            .filter_map(|name| {
+                let value = request.headers().get(name.as_str())?.to_str().ok()?;
+                Some((name.to_lowercase(), serde_json::Value::from(value)))
+            })
+            .collect();
+
+        RequestContext {
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            headers,
+        }
+    }
+}
+
+/// Verifies `Authorization: Bearer` tokens.
+pub struct Auth {
+    keys: Vec<Key>,
+    audience: Option<String>,
+    role_claim: String,
+    anonymous_role: Option<String>,
+}
+
+struct Key {
+    id: Option<String>,
+    algorithm: Algorithm,
+    decoding: DecodingKey,
+}
+
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|key| (&key.id, key.algorithm))
+            .collect();
+
+        f.debug_struct("Auth")
+            .field("keys", &keys)
+            .field("audience", &self.audience)
+            .field("role_claim", &self.role_claim)
+            .field("anonymous_role", &self.anonymous_role)
+            .finish()
+    }
+}
+
+impl Auth {
+    /// `None` when neither a secret nor a JWKS file is configured, requests then run as the
+    /// login role.
+    pub fn from_config(config: &AuthConfig) -> anyhow::Result<Option<Auth>> {
+        let mut keys = Vec::new();
+        if let Some(secret) = &config.jwt_secret {
+            keys.push(Key {
+                id: None,
+                algorithm: Algorithm::HS256,
+                decoding: DecodingKey::from_secret(secret.as_bytes()),
+            });
+        }
+        if let Some(path) = &config.jwks_file {
+            keys.extend(read_jwks(path)?);
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Auth {
+            keys,
+            audience: config.audience.clone(),
+            role_claim: config.role_claim.clone(),
+            anonymous_role: config.anonymous_role.clone(),
+        }))
+    }
+
+    /// Identity for the value of the `Authorization` header.
+    pub fn identify(&self, authorization: Option<&str>) -> Result<Identity> {
+        let Some(authorization) = authorization else {
+            return match &self.anonymous_role {
+                Some(role) => Ok(Identity {
+                    role: Some(role.clone()),
+                    ..Identity::default()
+                }),
+                None => Err(unauthorized("missing bearer token")),
+            };
+        };
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .ok_or_else(|| unauthorized("expected a bearer token"))?
+            .trim();
+
+        let claims = self.verify(token)?;
+        let role = match claims.get(&self.role_claim) {
+            Some(serde_json::Value::String(role)) => role.clone(),
+            Some(_) => {
+                return Err(unauthorized(format!(
+                    "claim {} is not a string",
+                    self.role_claim
+                )))
+            }
+            None => self.anonymous_role.clone().ok_or_else(|| {
+                MyError::from(anyhow::anyhow!("token has no {} claim", self.role_claim))
+                    .with_status(StatusCode::FORBIDDEN)
+            })?,
+        };
+
+        Ok(Identity {
+            role: Some(role),
+            claims,
+            ..Identity::default()
+        })
+    }
+
+    fn verify(&self, token: &str) -> Result<Claims> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| unauthorized(format!("invalid token: {e}")))?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                key.algorithm == header.alg
+                    && (key.id.is_none() || header.kid.is_none() || key.id == header.kid)
+            })
+            .ok_or_else(|| unauthorized(format!("no {:?} key to verify the token", header.alg)))?;
+
+        let mut validation = Validation::new(key.algorithm);
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+        let data = jsonwebtoken::decode::<Claims>(token, &key.decoding, &validation)
+            .map_err(|e| unauthorized(format!("invalid token: {e}")))?;
+
+        Ok(data.claims)
+    }
+}
+
+/// Keys of a JWKS file, keys without `alg` are RS256 or ES256 depending on their type.
+fn read_jwks(path: &Path) -> anyhow::Result<Vec<Key>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read jwks file {}", path.display()))?;
+    let jwks: JwkSet = serde_json::from_str(&contents)
+        .with_context(|| format!("invalid jwks file {}", path.display()))?;
+
+    jwks.keys
+        .iter()
+        .map(|jwk| {
+            let algorithm = match (jwk.common.algorithm, &jwk.algorithm) {
+                (Some(algorithm), _) => algorithm,
+                (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+                (None, AlgorithmParameters::EllipticCurve(_)) => Algorithm::ES256,
+                (None, _) => anyhow::bail!("jwk {:?} has no alg", jwk.common.key_id),
+            };
+
+            Ok(Key {
+                id: jwk.common.key_id.clone(),
+                algorithm,
+                decoding: DecodingKey::from_jwk(jwk)?,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn unauthorized(message: impl Display) -> MyError {
+    MyError::from(anyhow::anyhow!("{message}")).with_status(StatusCode::UNAUTHORIZED)
+}
+
+/// Middleware that verifies the api key or token and adds the `Identity` of the request,
+/// answers 401 for missing or invalid credentials. Without authentication configured
+/// requests still get an identity, with the login role. Failed attempts are rate limited
+/// by the IP address of the client, made up credentials do not get buckets of their own.
+pub async fn authenticate<B>(
+    State(state): State<AppState>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    if request.headers().contains_key(API_KEY_HEADER) && state.api_keys.has_table() {
+        // unknown keys are looked up in the database, refused once the IP is out of tokens
+        rate_limit::peek_client_ip(&state, &request)?;
+    }
+
+    let mut identity = match identify(&state, request.headers()).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            if e.status() == StatusCode::UNAUTHORIZED {
+                rate_limit::check_client_ip(&state, &request)?;
+            }
+            return Err(e);
+        }
+    };
+    identity.request = RequestContext::new(&request, &state.request_headers);
+
+    request.extensions_mut().insert(identity);
+    Ok(next.run(request).await)
+}
+
+async fn identify(state: &AppState, headers: &HeaderMap) -> Result<Identity> {
+    if let Some(api_key) = headers.get(API_KEY_HEADER) {
+        let api_key = api_key
+            .to_str()
+            .map_err(|_| unauthorized("invalid api key header"))?;
+        return state.api_keys.identify(&state.pool, api_key).await;
+    }
+    let Some(auth) = &state.auth else {
+        return Ok(Identity::default());
+    };
+    let authorization = match headers.get(AUTHORIZATION) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| unauthorized("invalid authorization header"))?,
+        ),
+        None => None,
+    };
+    auth.identify(authorization)
+}
+
+/// Starts the transaction a request runs in, with the request context set and switched to
+/// the role of the identity. A role the login role is not a member of fails with 42501,
+/// answered as 403. The `statement_timeout` only applies to this transaction. Returns the
+/// pid of the backend too, which the queries of the request can be cancelled with.
+pub async fn begin<'c>(
+    client: &'c mut PgConnection,
+    identity: &Identity,
+    statement_timeout: Option<Duration>,
+) -> Result<(Transaction<'c, Postgres>, i32)> {
+    let mut transaction = client.begin().await?;
+    let claims = serde_json::Value::Object(identity.claims.clone()).to_string();
+    let headers = serde_json::Value::Object(identity.request.headers.clone()).to_string();
+    let pid: i32 = query_scalar(SET_REQUEST_CONTEXT)
+        .bind(claims)
+        .bind(identity.request.method.as_str())
+        .bind(identity.request.path.as_str())
+        .bind(headers)
+        .bind(statement_timeout.map(|timeout| timeout.as_millis().to_string()))
+        .fetch_one(&mut *transaction)
+        .await?;
+    if let Some(role) = &identity.role {
+        let statement = format!("SET LOCAL ROLE {}", quote_ident(role));
+        transaction.execute(statement.as_str()).await?;
+    }
+
+    Ok((transaction, pid))
+}
+
+/// `"name"`, with embedded quotes doubled.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[test]
+fn auth_identify_hs256() {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    let config = AuthConfig {
+        jwt_secret: Some(String::from("secret")),
+        anonymous_role: Some(String::from("web_anon")),
+        ..AuthConfig::default()
+    };
+    let auth = Auth::from_config(&config).unwrap().unwrap();
+
+    let claims = serde_json::json!({"role": "web_user", "sub": "1", "exp": 4102444800u64});
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(b"secret"),
+    )
+    .unwrap();
+    let identity = auth.identify(Some(&format!("Bearer {token}"))).unwrap();
+    assert_eq!(identity.role.as_deref(), Some("web_user"));
+    assert_eq!(identity.claims["sub"], "1");
+
+    let identity = auth.identify(None).unwrap();
+    assert_eq!(identity.role.as_deref(), Some("web_anon"));
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(b"other"),
+    )
+    .unwrap();
+    let error = auth.identify(Some(&format!("Bearer {token}"))).unwrap_err();
+    assert_eq!(error.status(), StatusCode::UNAUTHORIZED);
+
+    let error = auth.identify(Some("Basic dXNlcjpwYXNz")).unwrap_err();
+    assert_eq!(error.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn auth_disabled_without_keys() {
+    assert!(Auth::from_config(&AuthConfig::default()).unwrap().is_none());
+    assert_eq!(quote_ident("we\"ird"), "\"we\"\"ird\"");
+}
+
+#[test]
+fn request_context_selected_headers() {
+    let request = Request::builder()
+        .method("PATCH")
+        .uri("/books/1?select=title")
+        .header("User-Agent", "curl/8.0")
+        .header("Cookie", "session=secret")
+        .body(())
+        .unwrap();
+
+    let context = RequestContext::new(&request, &[String::from("User-Agent")]);
+
+    assert_eq!(context.method, "PATCH");
+    assert_eq!(context.path, "/books/1");
+    assert_eq!(
+        serde_json::Value::Object(context.headers),
+        serde_json::json!({"user-agent": "curl/8.0"})
+    );
+}
+
+#[test]
+fn identity_rate_limit_key() {
+    let mut claims = Claims::new();
+    claims.insert(String::from("api_key"), "export".into());
+    let identity = Identity {
+        claims,
+        ..Identity::default()
+    };
+    assert_eq!(identity.rate_limit_key().as_deref(), Some("api_key:export"));
+
+    let mut claims = Claims::new();
+    claims.insert(String::from("sub"), "alice".into());
+    let identity = Identity {
+        claims,
+        ..Identity::default()
+    };
+    assert_eq!(identity.rate_limit_key().as_deref(), Some("sub:alice"));
+
+    assert_eq!(Identity::default().rate_limit_key(), None);
+}
@@ -1,17 +1,52 @@
 use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post},
     Router,
 };
-use deadpool_postgres::{Config, ManagerConfig, RecyclingMethod, Runtime};
-use restql_home::{get_record, insert_record, list_records, AppState};
+use clap::Parser;
+use deadpool_postgres::{ManagerConfig, RecyclingMethod, Runtime};
+use restql_home::api_key::ApiKeys;
+use restql_home::auth::{self, Auth};
+use restql_home::config::{Cli, Config};
+use restql_home::error::panic_response;
+use restql_home::policy::Policies;
+use restql_home::rate_limit::{self, RateLimits};
+use restql_home::schema::Schema;
+use restql_home::transform::Transforms;
+use restql_home::{cors, health, json_schema, logging, monitoring, shutdown, telemetry};
+use restql_home::{get_record, insert_record, list_records, root, update_record, AppState};
 use sqlx_core::{
     pool::PoolOptions,
     postgres::{PgConnectOptions, PgPool},
 };
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
 use tokio_postgres::NoTls;
+use tower::ServiceBuilder;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+
+/// `PgPool::close` waits for every checked out connection, abandoned requests may never
+/// give theirs back.
+const POOL_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
+
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml()?);
+        return Ok(());
+    }
+
+    logging::init(&config.log, telemetry::tracer(&config.tracing)?)?;
+
     // let mut cfg = Config::new();
     // cfg.dbname = Some("postgres".to_string());
     // cfg.user = Some("postgres".to_string());
@@ -22,29 +57,152 @@ async fn main() -> anyhow::Result<()> {
     //     recycling_method: RecyclingMethod::Fast,
     // });
     // let pool = cfg.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
-    let connect_opts = PgConnectOptions::new()
-        .host("localhost")
-        .port(5432)
-        .database("postgres")
-        .username("postgres")
-        .password("example");
+    let connect_opts = PgConnectOptions::from_str(&config.database_url)?
+        .options([("search_path", config.schemas.join(","))]);
 
-    let pool_opts = PoolOptions::new();
-    let pool = pool_opts.connect_with(connect_opts).await?;
+    let pool_opts = PoolOptions::new()
+        .max_connections(config.pool.max_connections)
+        .min_connections(config.pool.min_connections)
+        .acquire_timeout(config.pool.acquire_timeout())
+        .idle_timeout(config.pool.idle_timeout())
+        .max_lifetime(config.pool.max_lifetime());
+    let pool = pool_opts.connect_with(connect_opts.clone()).await?;
 
     // let (client, connection) = tokio_postgres::connect("host=localhost user=postgres password=example", NoTls).await?;
 
-    let shared_state = AppState { pool };
+    let schema = Schema::discover_all(&pool, &config.schemas).await;
+    // nothing reloads the schema later, a server without it would never become ready
+    if !schema.discovered {
+        anyhow::bail!("schema introspection failed, see the errors logged above");
+    }
+    let validators = json_schema::Validators::new(&schema)?;
+    let transforms = Transforms::new(config.transforms.clone(), &schema)?;
+    let policies = match &config.auth.policy_file {
+        Some(path) => Policies::from_file(path)?,
+        None => Policies::default(),
+    };
 
-    let app = Router::new()
-        .route("/", get(|| async { "Hello, World!" }))
+    let shared_state = AppState {
+        pool,
+        schema: Arc::new(schema),
+        validators: Arc::new(validators),
+        transforms: Arc::new(transforms),
+        binary_content_types: Arc::new(config.binary_content_types.clone()),
+        redact_columns: Arc::new(config.log.redact_columns.clone()),
+        auth: Auth::from_config(&config.auth)?.map(Arc::new),
+        api_keys: Arc::new(ApiKeys::from_config(&config.auth)),
+        policies: Arc::new(policies),
+        request_headers: Arc::new(config.auth.request_headers.clone()),
+        rate_limits: Arc::new(RateLimits::new(config.rate_limit.clone())),
+        limits: Arc::new(config.limits.clone()),
+        statement_timeouts: Arc::new(config.statement_timeout.clone()),
+        connect_options: Arc::new(connect_opts),
+        transactions: Default::default(),
+        metrics: Some(monitoring::install_recorder()?),
+    };
+    let pool = shared_state.pool.clone();
+    let transactions = shared_state.transactions.clone();
+
+    // merged after the rate limit route layer, so probes and scrapes are never limited
+    let mut operational = Router::new().route("/metrics", get(monitoring::metrics));
+    if !config.endpoints.health_live.is_empty() {
+        operational = operational.route(&config.endpoints.health_live, get(health::live));
+    }
+    if !config.endpoints.health_ready.is_empty() {
+        operational = operational.route(&config.endpoints.health_ready, get(health::ready));
+    }
+    if !config.endpoints.version.is_empty() {
+        operational = operational.route(&config.endpoints.version, get(health::version));
+    }
+
+    // only the tables need a token or api key, the schema documents and operational
+    // endpoints are public. The tables are rate limited after authentication, by the
+    // verified client instead of its IP address
+    let tables = Router::new()
         .route("/:table_name", post(insert_record).get(list_records))
-        .route("/:table_name/:record_id", get(get_record))
+        .route(
+            "/:table_name/:record_id",
+            get(get_record).patch(update_record),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            rate_limit::limit_requests,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            auth::authenticate,
+        ));
+
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/_schema/:table_name", get(json_schema::table_json_schema))
+        .route_layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            rate_limit::limit_requests,
+        ))
+        .merge(operational)
+        .merge(tables)
+        .layer(DefaultBodyLimit::max(config.limits.max_body_bytes))
+        .layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            monitoring::track_requests,
+        ))
+        .layer(CatchPanicLayer::custom(panic_response))
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(logging::make_span))
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
         .with_state(shared_state);
+    // outermost, so preflights are answered before routing and rate limits
+    let app = match cors::layer(&config.cors)? {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+
+    let stop_accepting = Arc::new(Notify::new());
+    tracing::info!(listen = %config.listen, "listening");
+    let server = axum::Server::bind(&config.listen)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown({
+            let stop_accepting = stop_accepting.clone();
+            async move { stop_accepting.notified().await }
+        });
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => result?,
+        _ = shutdown::signal() => {
+            tracing::info!("shutting down, draining in-flight requests");
+            stop_accepting.notify_one();
+
+            let deadline = tokio::time::Instant::now() + config.shutdown_timeout();
+            let drained = async {
+                let result = (&mut server).await;
+                transactions.wait_idle().await;
+                result
+            };
+            match tokio::time::timeout_at(deadline, drained).await {
+                Ok(result) => result?,
+                Err(_) => tracing::warn!(
+                    transactions = transactions.count(),
+                    "shutdown deadline elapsed, abandoning in-flight requests"
+                ),
+            }
+        }
+    }
 
-    axum::Server::bind(&"0.0.0.0:9503".parse().unwrap())
-        .serve(app.into_make_service())
-        .await?;
+    if tokio::time::timeout(POOL_CLOSE_TIMEOUT, pool.close())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            connections = pool.size(),
+            "connections still in use, exiting without closing them"
+        );
+    }
+    telemetry::shutdown();
 
     Ok(())
 }
@@ -0,0 +1,167 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hyper::StatusCode;
+use sqlx_core::connection::Connection;
+use sqlx_core::postgres::{PgConnectOptions, PgConnection};
+use sqlx_core::query::query;
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use crate::auth::{self, Identity};
+use crate::config::StatementTimeoutConfig;
+use crate::{AppState, MyError, Result};
+
+/// Cancelling is given up when the database does not accept a connection in time.
+const CANCEL_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The `statement_timeout` of a request, the one of the table wins over the one of the
+/// role, which wins over `default_ms`. `None` leaves the setting of the database alone.
+pub fn statement_timeout(
+    config: &StatementTimeoutConfig,
+    role: Option<&str>,
+    table_name: &str,
+) -> Option<Duration> {
+    config
+        .tables
+        .get(table_name)
+        .or_else(|| role.and_then(|role| config.roles.get(role)))
+        .copied()
+        .or(config.default_ms)
+        .map(Duration::from_millis)
+}
+
+/// Runs `work` in the transaction of the request on its own task, and commits when it
+/// succeeds. When the client disconnects hyper drops the handler, the running query is then
+/// cancelled with `pg_cancel_backend` instead of holding on to the connection until it
+/// finishes.
+pub async fn transaction<T, F>(
+    state: AppState,
+    identity: Identity,
+    statement_timeout: Option<Duration>,
+    work: F,
+) -> Result<T>
+where
+    T: Send + 'static,
+    F: for<'t> FnOnce(&'t mut PgConnection, &'t Identity, &'t AppState) -> BoxFuture<'t, Result<T>>
+        + Send
+        + 'static,
+{
+    let mut client = state.acquire().await?;
+
+    // the pid while the request holds the connection, a cancel after it went back to the
+    // pool could hit the query of another request
+    let running = Arc::new(Mutex::new(None));
+    let mut guard = CancelGuard {
+        connect_options: state.connect_options.clone(),
+        running: running.clone(),
+        armed: true,
+    };
+    let task = tokio::spawn(
+        async move {
+            let result = AssertUnwindSafe(async {
+                let (mut transaction, pid) =
+                    auth::begin(&mut client, &identity, statement_timeout).await?;
+                *running.lock().await = Some(pid);
+                let value = work(&mut transaction, &identity, &state).await?;
+                transaction.commit().await?;
+                Ok::<_, MyError>(value)
+            })
+            .catch_unwind()
+            .await;
+            *running.lock().await = None;
+            drop(client);
+            result
+        }
+        .in_current_span(),
+    );
+
+    let result = task.await;
+    guard.armed = false;
+    match result {
+        Ok(Ok(result)) => result,
+        // left to the CatchPanicLayer, like panics of the handler itself
+        Ok(Err(panic)) => std::panic::resume_unwind(panic),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(MyError::from(anyhow::anyhow!("the request was cancelled"))
+            .with_status(StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+struct CancelGuard {
+    connect_options: Arc<PgConnectOptions>,
+    running: Arc<Mutex<Option<i32>>>,
+    armed: bool,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let connect_options = self.connect_options.clone();
+        let running = self.running.clone();
+
+        tokio::spawn(async move {
+            // holding the lock keeps the request from giving the connection back meanwhile
+            let running = running.lock().await;
+            let Some(pid) = *running else {
+                return;
+            };
+            tracing::info!(pid, "client disconnected, cancelling its query");
+            if let Err(e) = cancel_backend(&connect_options, pid).await {
+                tracing::warn!(error = ?e.source, pid, "cancelling the query failed");
+            }
+        });
+    }
+}
+
+/// Cancels on a connection of its own, the pool is likely exhausted by the slow queries
+/// that need cancelling.
+async fn cancel_backend(connect_options: &PgConnectOptions, pid: i32) -> Result<()> {
+    let mut connection = tokio::time::timeout(
+        CANCEL_CONNECT_TIMEOUT,
+        PgConnection::connect_with(connect_options),
+    )
+    .await??;
+    query("SELECT pg_cancel_backend($1)")
+        .bind(pid)
+        .execute(&mut connection)
+        .await?;
+    connection.close().await?;
+    Ok(())
+}
+
+#[test]
+fn statement_timeout_per_role_and_table() {
+    let config: StatementTimeoutConfig = toml::from_str(
+        r#"
+        default_ms = 5000
+
+        [roles]
+        reporting = 60000
+
+        [tables]
+        audit_log = 0
+        "#,
+    )
+    .unwrap();
+
+    let timeout = |role, table| statement_timeout(&config, role, table);
+    assert_eq!(timeout(None, "books"), Some(Duration::from_secs(5)));
+    assert_eq!(
+        timeout(Some("reporting"), "books"),
+        Some(Duration::from_secs(60))
+    );
+    assert_eq!(
+        timeout(Some("reporting"), "audit_log"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(
+        statement_timeout(&StatementTimeoutConfig::default(), None, "books"),
+        None
+    );
+}
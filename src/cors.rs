@@ -0,0 +1,105 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::Method;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer, ExposeHeaders};
+
+use crate::config::CorsConfig;
+
+const WILDCARD: &str = "*";
+
+/// Layer answering preflights and adding the CORS headers, `None` when no origin is
+/// allowed. Wrapped around the whole router, so preflights never reach the table routes.
+pub fn layer(config: &CorsConfig) -> anyhow::Result<Option<CorsLayer>> {
+    if config.allowed_origins.is_empty() {
+        return Ok(None);
+    }
+    let wildcards = [
+        &config.allowed_origins,
+        &config.allowed_methods,
+        &config.allowed_headers,
+        &config.exposed_headers,
+    ];
+    if config.allow_credentials && wildcards.iter().any(|values| is_wildcard(values)) {
+        anyhow::bail!("cors: `*` cannot be combined with allow_credentials, list the values");
+    }
+
+    let origins = if is_wildcard(&config.allowed_origins) {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(parse_all(&config.allowed_origins, HeaderValue::from_str)?)
+    };
+    let methods = if is_wildcard(&config.allowed_methods) {
+        AllowMethods::from(Any)
+    } else {
+        AllowMethods::list(parse_all(&config.allowed_methods, |method| {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+        })?)
+    };
+    let headers = if is_wildcard(&config.allowed_headers) {
+        AllowHeaders::from(Any)
+    } else {
+        AllowHeaders::list(parse_all(&config.allowed_headers, HeaderName::from_str)?)
+    };
+    let exposed = if is_wildcard(&config.exposed_headers) {
+        ExposeHeaders::from(Any)
+    } else {
+        ExposeHeaders::list(parse_all(&config.exposed_headers, HeaderName::from_str)?)
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(exposed)
+        .allow_credentials(config.allow_credentials);
+    if config.max_age_secs > 0 {
+        layer = layer.max_age(Duration::from_secs(config.max_age_secs));
+    }
+
+    Ok(Some(layer))
+}
+
+fn is_wildcard(values: &[String]) -> bool {
+    values.iter().any(|value| value == WILDCARD)
+}
+
+fn parse_all<T, E>(
+    values: &[String],
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> anyhow::Result<Vec<T>>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    values
+        .iter()
+        .map(|value| parse(value).with_context(|| format!("cors: invalid value {value:?}")))
+        .collect()
+}
+
+#[test]
+fn cors_layer_from_config() {
+    assert!(layer(&CorsConfig::default()).unwrap().is_none());
+
+    let config = CorsConfig {
+        allowed_origins: vec![String::from("https://app.example.com")],
+        allow_credentials: true,
+        ..CorsConfig::default()
+    };
+    assert!(layer(&config).unwrap().is_some());
+
+    let config = CorsConfig {
+        allowed_origins: vec![String::from(WILDCARD)],
+        ..config
+    };
+    assert!(layer(&config).is_err());
+
+    let config = CorsConfig {
+        allowed_origins: vec![String::from("https://app.example.com")],
+        allowed_methods: vec![String::from("GET"), String::from("not a method")],
+        ..CorsConfig::default()
+    };
+    assert!(layer(&config).is_err());
+}
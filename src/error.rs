@@ -1,25 +1,138 @@
+use std::any::Any;
+
 use axum::response::{IntoResponse, Response};
+use axum::Json;
+use hyper::header::{HeaderValue, RETRY_AFTER, WWW_AUTHENTICATE};
 use hyper::StatusCode;
+use sqlx_core::postgres::PgDatabaseError;
 
 pub type Result<T> = std::result::Result<T, MyError>;
 
+/// Sent as `Retry-After` with 503 responses, when the pool or database is unavailable.
+const RETRY_AFTER_SECONDS: &str = "1";
+
 #[derive(Debug)]
 // #[error(transparent)]
 pub struct MyError {
     pub source: anyhow::Error,
+    /// Overrides the status that is derived from the source
+    pub status: Option<StatusCode>,
+    /// Seconds sent as `Retry-After`, like when a rate limit is exceeded
+    pub retry_after: Option<u64>,
 }
 
 impl<E: Into<anyhow::Error>> From<E> for MyError {
     fn from(err: E) -> MyError {
-        MyError { source: err.into() }
+        MyError {
+            source: err.into(),
+            status: None,
+            retry_after: None,
+        }
+    }
+}
+
+/// Json body of every error response, modelled after postgrest.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct ErrorBody {
+    pub code: Option<String>,
+    pub message: String,
+    pub details: Option<String>,
+    pub hint: Option<String>,
+}
+
+impl MyError {
+    pub fn with_status(mut self, status: StatusCode) -> MyError {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> MyError {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        if let Some(status) = self.status {
+            return status;
+        }
+
+        match self.source.downcast_ref::<sqlx_core::error::Error>() {
+            Some(sqlx_core::error::Error::Database(error)) => match error.code() {
+                Some(code) => status_from_sqlstate(&code),
+                None => StatusCode::BAD_REQUEST,
+            },
+            Some(sqlx_core::error::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            Some(
+                sqlx_core::error::Error::Io(_)
+                | sqlx_core::error::Error::Tls(_)
+                | sqlx_core::error::Error::PoolTimedOut
+                | sqlx_core::error::Error::PoolClosed
+                | sqlx_core::error::Error::WorkerCrashed,
+            ) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let database_error = match self.source.downcast_ref::<sqlx_core::error::Error>() {
+            Some(sqlx_core::error::Error::Database(error)) => {
+                error.try_downcast_ref::<PgDatabaseError>()
+            }
+            _ => None,
+        };
+
+        match database_error {
+            Some(error) => ErrorBody {
+                code: Some(error.code().to_string()),
+                message: error.message().to_string(),
+                details: error.detail().map(String::from),
+                hint: error.hint().map(String::from),
+            },
+            None => ErrorBody {
+                code: None,
+                message: self.source.to_string(),
+                details: None,
+                hint: None,
+            },
+        }
+    }
+}
+
+fn status_from_sqlstate(code: &str) -> StatusCode {
+    match code {
+        "23505" | "23503" => StatusCode::CONFLICT,
+        "42P01" => StatusCode::NOT_FOUND,
+        "42703" => StatusCode::BAD_REQUEST,
+        "42501" => StatusCode::FORBIDDEN,
+        // statement_timeout or pg_cancel_backend
+        "57014" => StatusCode::GATEWAY_TIMEOUT,
+        // too many connections and connection exceptions
+        "53300" => StatusCode::SERVICE_UNAVAILABLE,
+        code if code.starts_with("08") => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
     }
 }
 
 impl IntoResponse for MyError {
     fn into_response(self) -> Response {
-        let mut res = self.source.to_string().into_response();
-        *res.status_mut() = StatusCode::BAD_REQUEST;
-        res
+        let status = self.status();
+        let mut response = (status, Json(self.body())).into_response();
+        if let Some(seconds) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        } else if status == StatusCode::SERVICE_UNAVAILABLE {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECONDS));
+        }
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+
+        response
     }
 }
 
@@ -28,3 +141,64 @@ impl From<MyError> for mlua::Error {
         mlua::Error::RuntimeError(err.source.to_string())
     }
 }
+
+/// Used by the `CatchPanicLayer`, so a panicking handler answers with a 500 instead of
+/// dropping the connection.
+pub fn panic_response(err: Box<dyn Any + Send + 'static>) -> Response {
+    let message = if let Some(message) = err.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = err.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        String::from("unknown panic")
+    };
+
+    let body = ErrorBody {
+        code: None,
+        message: format!("internal server error: {message}"),
+        details: None,
+        hint: None,
+    };
+
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+}
+
+#[test]
+fn status_from_sqlstate_mapping() {
+    assert_eq!(status_from_sqlstate("23505"), StatusCode::CONFLICT);
+    assert_eq!(status_from_sqlstate("23503"), StatusCode::CONFLICT);
+    assert_eq!(status_from_sqlstate("42P01"), StatusCode::NOT_FOUND);
+    assert_eq!(status_from_sqlstate("42703"), StatusCode::BAD_REQUEST);
+    assert_eq!(status_from_sqlstate("42501"), StatusCode::FORBIDDEN);
+    assert_eq!(status_from_sqlstate("57014"), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(
+        status_from_sqlstate("08006"),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[test]
+fn pool_errors_are_unavailable() {
+    let error = MyError::from(sqlx_core::error::Error::PoolTimedOut);
+    assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = error.into_response();
+    assert_eq!(response.headers()[RETRY_AFTER], RETRY_AFTER_SECONDS);
+}
+
+#[test]
+fn error_body_for_application_errors() {
+    let error = MyError::from(anyhow::anyhow!("table nope does not exist"))
+        .with_status(StatusCode::NOT_FOUND);
+
+    assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        error.body(),
+        ErrorBody {
+            code: None,
+            message: String::from("table nope does not exist"),
+            details: None,
+            hint: None,
+        }
+    );
+}
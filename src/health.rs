@@ -0,0 +1,65 @@
+use axum::extract::State;
+use axum::Json;
+use hyper::StatusCode;
+use serde::Serialize;
+use sqlx_core::query_scalar::query_scalar;
+
+use crate::{AppState, MyError, Result};
+
+#[derive(Serialize, Debug)]
+pub struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Version {
+    name: &'static str,
+    version: &'static str,
+    /// Set with `RESTQL_BUILD_COMMIT` at compile time
+    commit: Option<&'static str>,
+    profile: &'static str,
+    postgres: String,
+}
+
+/// Handler for `/health/live`, answers as long as the process runs.
+pub async fn live() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+/// Handler for `/health/ready`, checks the schema is introspected and a connection can be
+/// acquired.
+pub async fn ready(State(state): State<AppState>) -> Result<Json<Health>> {
+    if !state.schema.discovered {
+        return Err(
+            MyError::from(anyhow::anyhow!("schema introspection has not completed"))
+                .with_status(StatusCode::SERVICE_UNAVAILABLE),
+        );
+    }
+
+    let mut client = state.acquire().await?;
+    query_scalar::<_, i32>("SELECT 1")
+        .fetch_one(&mut *client)
+        .await?;
+
+    Ok(Json(Health { status: "ready" }))
+}
+
+/// Handler for `/version`.
+pub async fn version(State(state): State<AppState>) -> Result<Json<Version>> {
+    let mut client = state.acquire().await?;
+    let postgres = query_scalar::<_, String>("SHOW server_version")
+        .fetch_one(&mut *client)
+        .await?;
+
+    Ok(Json(Version {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        commit: option_env!("RESTQL_BUILD_COMMIT"),
+        profile: if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        },
+        postgres,
+    }))
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use jsonschema::{Draft, JSONSchema};
+use sea_schema::postgres::def::TableDef;
+use serde_json::{json, Value as JsonValue};
+
+use crate::schema::Schema;
+use crate::{openapi, AppState, MyError, Result};
+
+pub const DRAFT_2020_12: &str = "https://json-schema.org/draft/2020-12/schema";
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    #[default]
+    Insert,
+    Update,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SchemaParams {
+    #[serde(default)]
+    operation: Operation,
+}
+
+/// Handler for `/_schema/:table_name`, `?operation=update` gives the schema for updates.
+pub async fn table_json_schema(
+    Path(table_name): Path<String>,
+    Query(params): Query<SchemaParams>,
+    State(state): State<AppState>,
+) -> Result<Json<JsonValue>> {
+    let table = state.schema.table(&table_name)?;
+
+    Ok(Json(table_schema(&state.schema, table, params.operation)))
+}
+
+/// Schema of insert or update payloads, for updates no column is required.
+pub fn table_schema(schema: &Schema, table: &TableDef, operation: Operation) -> JsonValue {
+    let mut table_schema = openapi::table_schema(schema, table);
+    if operation == Operation::Update {
+        if let Some(object) = table_schema.as_object_mut() {
+            object.remove("required");
+        }
+    }
+    table_schema["$schema"] = json!(DRAFT_2020_12);
+    table_schema["title"] = json!(table.info.name);
+
+    table_schema
+}
+
+/// Compiled schemas of every table, used to validate bodies before they reach the database.
+#[derive(Debug, Default)]
+pub struct Validators {
+    tables: HashMap<(String, Operation), JSONSchema>,
+}
+
+impl Validators {
+    pub fn new(schema: &Schema) -> anyhow::Result<Validators> {
+        let mut tables = HashMap::new();
+        for (table_name, table) in &schema.tables {
+            for operation in [Operation::Insert, Operation::Update] {
+                let table_schema = table_schema(schema, table, operation);
+                let validator = JSONSchema::options()
+                    .with_draft(Draft::Draft202012)
+                    .should_validate_formats(false)
+                    .compile(&table_schema)
+                    .map_err(|e| anyhow::anyhow!("invalid json schema for {table_name}: {e}"))?;
+                tables.insert((table_name.clone(), operation), validator);
+            }
+        }
+
+        Ok(Validators { tables })
+    }
+
+    /// Checks a request body, answers 400 listing every violation.
+    pub fn validate(&self, table_name: &str, operation: Operation, body: &JsonValue) -> Result<()> {
+        let Some(validator) = self.tables.get(&(table_name.to_string(), operation)) else {
+            return Ok(());
+        };
+
+        if let Err(errors) = validator.validate(body) {
+            let errors = errors
+                .map(|error| match error.instance_path.to_string() {
+                    path if path.is_empty() => error.to_string(),
+                    path => format!("{path}: {error}"),
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(MyError::from(anyhow::anyhow!(
+                "invalid body for {table_name}: {errors}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// JSON Schema keywords for the simple CHECK constraints, like `(rating >= 1)` or
+/// `(char_length((username)::text) <= 20)`. Expressions that cannot be expressed are skipped.
+pub fn check_keywords(table: &TableDef) -> Vec<(String, &'static str, JsonValue)> {
+    table
+        .check_constraints
+        .iter()
+        .flat_map(|check| split_and(strip_parens(check.expr.trim())))
+        .filter_map(|comparison| comparison_keyword(strip_parens(comparison)))
+        .collect()
+}
+
+/// Removes the parentheses around the whole expression.
+fn strip_parens(expression: &str) -> &str {
+    let mut expression = expression.trim();
+    while expression.starts_with('(')
+        && expression.ends_with(')')
+        && closing_paren(expression) == Some(expression.len() - 1)
+    {
+        expression = expression[1..expression.len() - 1].trim();
+    }
+    expression
+}
+
+fn closing_paren(expression: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, character) in expression.char_indices() {
+        match character {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Splits on the top level `AND`s.
+fn split_and(expression: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, character) in expression.char_indices() {
+        match character {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && expression[index..].starts_with(" AND ") => {
+                parts.push(&expression[start..index]);
+                start = index + " AND ".len();
+            }
+            _ => (),
+        }
+    }
+    parts.push(&expression[start..]);
+    parts
+}
+
+fn comparison_keyword(comparison: &str) -> Option<(String, &'static str, JsonValue)> {
+    let (left, operator, right) = [">=", "<=", ">", "<"].into_iter().find_map(|operator| {
+        comparison
+            .split_once(&format!(" {operator} "))
+            .map(|(left, right)| (left.trim(), operator, right.trim()))
+    })?;
+    let number = strip_cast(right).trim_matches('\'').parse::<f64>().ok()?;
+
+    if let Some(column) = length_argument(left) {
+        let length = number as i64;
+        let (keyword, length) = match operator {
+            ">=" => ("minLength", length),
+            ">" => ("minLength", length + 1),
+            "<=" => ("maxLength", length),
+            _ => ("maxLength", length - 1),
+        };
+        return Some((column.to_string(), keyword, json!(length.max(0))));
+    }
+
+    let column = strip_cast(left);
+    if !column.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let keyword = match operator {
+        ">=" => "minimum",
+        ">" => "exclusiveMinimum",
+        "<=" => "maximum",
+        _ => "exclusiveMaximum",
+    };
+    let number = if number.fract() == 0.0 {
+        json!(number as i64)
+    } else {
+        json!(number)
+    };
+
+    Some((column.to_string(), keyword, number))
+}
+
+/// `char_length((username)::text)` -> `username`
+fn length_argument(expression: &str) -> Option<&str> {
+    let argument = ["char_length(", "length(", "character_length("]
+        .into_iter()
+        .find_map(|function| expression.strip_prefix(function))?
+        .strip_suffix(')')?;
+    let column = strip_cast(argument);
+
+    column
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_')
+        .then_some(column)
+}
+
+/// `(0)::numeric` -> `0`
+fn strip_cast(expression: &str) -> &str {
+    let expression = match expression.split_once("::") {
+        Some((value, _cast)) => value,
+        None => expression,
+    };
+    strip_parens(expression)
+}
+
+#[test]
+fn check_keywords_from_constraints() {
+    use sea_schema::postgres::def::{Check, TableInfo};
+
+    let check = |expr: &str| Check {
+        name: String::from("check"),
+        expr: expr.to_string(),
+        no_inherit: false,
+    };
+    let table = TableDef {
+        info: TableInfo {
+            name: String::from("reviews"),
+            of_type: None,
+        },
+        columns: Vec::new(),
+        check_constraints: vec![
+            check("((rating >= 1) AND (rating <= 5))"),
+            check("(char_length((username)::text) > 2)"),
+            check("(price > (0)::numeric)"),
+            check("((status)::text = ANY ((ARRAY['a'::character varying])::text[]))"),
+            check("rating IS NOT NULL"),
+        ],
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    };
+
+    assert_eq!(
+        check_keywords(&table),
+        vec![
+            (String::from("rating"), "minimum", json!(1)),
+            (String::from("rating"), "maximum", json!(5)),
+            (String::from("username"), "minLength", json!(3)),
+            (String::from("price"), "exclusiveMinimum", json!(0)),
+        ]
+    );
+}
+
+#[test]
+fn validators_check_lengths_and_required() {
+    use sea_schema::postgres::def::{
+        Check, ColumnInfo, ColumnType, NotNull, StringAttr, TableInfo,
+    };
+
+    let column = |name: &str, col_type: ColumnType| ColumnInfo {
+        name: name.to_string(),
+        col_type,
+        default: None,
+        generated: None,
+        not_null: Some(NotNull),
+        is_identity: false,
+    };
+    let table = TableDef {
+        info: TableInfo {
+            name: String::from("accounts"),
+            of_type: None,
+        },
+        columns: vec![
+            column(
+                "username",
+                ColumnType::Varchar(StringAttr { length: Some(5) }),
+            ),
+            column("rating", ColumnType::Integer),
+        ],
+        check_constraints: vec![Check {
+            name: String::from("accounts_rating_check"),
+            expr: String::from("((rating >= 1) AND (rating <= 5))"),
+            no_inherit: false,
+        }],
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    };
+    let mut schema = Schema::default();
+    schema.tables.insert(String::from("accounts"), table);
+    let validators = Validators::new(&schema).unwrap();
+
+    let valid = json!({"username": "hoi", "rating": 5});
+    assert!(validators
+        .validate("accounts", Operation::Insert, &valid)
+        .is_ok());
+
+    let error = validators
+        .validate(
+            "accounts",
+            Operation::Insert,
+            &json!({"username": "toolong"}),
+        )
+        .unwrap_err();
+    let message = error.body().message;
+    assert!(
+        message.contains("\"rating\" is a required property"),
+        "{message}"
+    );
+    assert!(
+        message.contains("/username: \"toolong\" is longer than 5 characters"),
+        "{message}"
+    );
+
+    assert!(validators
+        .validate("accounts", Operation::Update, &json!({"rating": 3}))
+        .is_ok());
+    assert!(validators
+        .validate("accounts", Operation::Update, &json!({"rating": 6}))
+        .is_err());
+    // numbers sent as text are coerced later, like in filters
+    assert!(validators
+        .validate("accounts", Operation::Update, &json!({"rating": "3"}))
+        .is_ok());
+    assert!(validators
+        .validate("accounts", Operation::Update, &json!({"rating": "three"}))
+        .is_err());
+}
@@ -1,9 +1,32 @@
-use axum::extract::{Json, Path, RawQuery, State};
+use axum::extract::{Extension, Json, Path, Query, RawQuery, State};
+use axum::response::{IntoResponse, Response};
 use either::Either;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::{HeaderMap, StatusCode};
 use postgrest_query_parser::{Ast, Lexer};
+use std::collections::HashMap;
+use std::sync::Arc;
+pub mod api_key;
+pub mod auth;
+pub mod cancel;
+pub mod config;
+pub mod cors;
 pub mod error;
+pub mod health;
+pub mod json_schema;
+pub mod limits;
+pub mod logging;
 pub mod methods;
+pub mod monitoring;
+pub mod openapi;
+pub mod policy;
+pub mod pool;
+pub mod rate_limit;
+pub mod schema;
 pub mod scripting;
+pub mod shutdown;
+pub mod telemetry;
+pub mod transform;
 pub mod value;
 
 pub use error::{MyError, Result};
@@ -21,53 +44,225 @@ pub struct InsertBody {
 pub struct AppState {
     // pub pool: deadpool_postgres::Pool,
     pub pool: sqlx_core::pool::Pool<sqlx_core::postgres::Postgres>,
+    pub schema: Arc<schema::Schema>,
+    /// Content types for raw bytea downloads, keyed by `table.column`
+    pub binary_content_types: Arc<HashMap<String, String>>,
+    /// Columns whose values are never logged, either `column` or `table.column`
+    pub redact_columns: Arc<Vec<String>>,
+    /// Compiled JSON Schemas that insert and update bodies are validated against
+    pub validators: Arc<json_schema::Validators>,
+    /// Hashing, normalisation and generated values applied to written columns
+    pub transforms: Arc<transform::Transforms>,
+    /// Verifies bearer tokens, `None` when authentication is not configured
+    pub auth: Option<Arc<auth::Auth>>,
+    /// Keys accepted in the `X-Api-Key` header
+    pub api_keys: Arc<api_key::ApiKeys>,
+    /// Table and column permissions per role
+    pub policies: Arc<policy::Policies>,
+    /// Headers published to postgres as `request.headers`
+    pub request_headers: Arc<Vec<String>>,
+    /// Token buckets per client and the permits for Lua scripts
+    pub rate_limits: Arc<rate_limit::RateLimits>,
+    /// Body, bulk, select and row limits of requests
+    pub limits: Arc<config::LimitsConfig>,
+    /// `statement_timeout` of request transactions, per role and table
+    pub statement_timeouts: Arc<config::StatementTimeoutConfig>,
+    /// Opens the connections that cancel the queries of disconnected clients
+    pub connect_options: Arc<sqlx_core::postgres::PgConnectOptions>,
+    /// Running Lua script transactions, drained on shutdown
+    pub transactions: Arc<shutdown::InFlight>,
+    /// Renders `/metrics`, `None` when no recorder is installed
+    pub metrics: Option<metrics_exporter_prometheus::PrometheusHandle>,
+}
+
+impl AppState {
+    pub async fn acquire(
+        &self,
+    ) -> Result<sqlx_core::pool::PoolConnection<sqlx_core::postgres::Postgres>> {
+        pool::acquire(&self.pool).await
+    }
+
+    /// The `statement_timeout` for a request of the identity on the table.
+    pub fn statement_timeout(
+        &self,
+        identity: &auth::Identity,
+        table_name: &str,
+    ) -> Option<std::time::Duration> {
+        cancel::statement_timeout(
+            &self.statement_timeouts,
+            identity.role.as_deref(),
+            table_name,
+        )
+    }
+
+    pub fn binary_content_type(&self, table_name: &str, column_name: &str) -> String {
+        self.binary_content_types
+            .get(&format!("{table_name}.{column_name}"))
+            .cloned()
+            .unwrap_or_else(|| String::from(OCTET_STREAM))
+    }
+}
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+fn accepts(headers: &HeaderMap, content_type: &str) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(content_type))
+}
+
+/// Handler for `/`, serves the OpenAPI document when asked for with
+/// `Accept: application/openapi+json`.
+pub async fn root(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    if accepts(&headers, openapi::OPENAPI_CONTENT_TYPE) {
+        let document = openapi::document(&state.schema);
+        return (
+            [(CONTENT_TYPE, openapi::OPENAPI_CONTENT_TYPE)],
+            Json(document),
+        )
+            .into_response();
+    }
+
+    "Hello, World!".into_response()
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GetParams {
+    column: Option<String>,
+}
+
+fn accepts_octet_stream(headers: &HeaderMap) -> bool {
+    accepts(headers, OCTET_STREAM)
 }
 
 pub async fn get_record(
     Path((table_name, record_id)): Path<(String, String)>,
+    Query(params): Query<GetParams>,
+    headers: HeaderMap,
+    Extension(identity): Extension<auth::Identity>,
     State(state): State<AppState>,
-) -> Result<Json<Option<OptionalJsonMap>>> {
-    // let client = state.pool.get().await?;
-    let mut client = state.pool.acquire().await?;
-    let result = methods::get_record(&mut client, (table_name, record_id), state).await?;
+) -> Result<Response> {
+    let timeout = state.statement_timeout(&identity, &table_name);
+    let column = params.column.filter(|_| accepts_octet_stream(&headers));
+
+    cancel::transaction(state, identity, timeout, move |client, identity, state| {
+        Box::pin(async move {
+            if let Some(column) = column {
+                let content_type = state.binary_content_type(&table_name, &column);
+                let result = methods::get_record_bytes(
+                    client,
+                    (table_name, record_id),
+                    column,
+                    identity,
+                    state.clone(),
+                )
+                .await?;
 
-    Ok(Json(result))
+                return match result {
+                    Some(bytes) => Ok(([(CONTENT_TYPE, content_type)], bytes).into_response()),
+                    None => Ok(StatusCode::NOT_FOUND.into_response()),
+                };
+            }
+
+            let result =
+                methods::get_record(client, (table_name, record_id), identity, state.clone())
+                    .await?;
+
+            Ok(Json(result).into_response())
+        })
+    })
+    .await
 }
 
 #[axum::debug_handler]
 pub async fn list_records(
     Path(table_name): Path<String>,
     params: RawQuery,
+    Extension(identity): Extension<auth::Identity>,
     State(state): State<AppState>,
-) -> Result<Json<Vec<OptionalJsonMap>>> {
-    let params = if let Some(params) = params.0 {
+) -> Result<Response> {
+    let (params, filters) = if let Some(params) = params.0 {
+        let (params, filters) = methods::sql::split_params(&params)?;
         let lexer = Lexer::new(params.chars());
-        Ast::from_lexer(&params, lexer)?
+        (Ast::from_lexer(&params, lexer)?, filters)
     } else {
-        Ast::default()
+        (Ast::default(), Vec::new())
     };
+    limits::check_ast(&params, &state.limits)?;
+    let timeout = state.statement_timeout(&identity, &table_name);
 
-    // let client = state.pool.get().await?;
-    let mut client = state.pool.acquire().await?;
-    let result = methods::list_records(&mut client, table_name, params, state).await?;
+    cancel::transaction(state, identity, timeout, move |client, identity, state| {
+        Box::pin(async move {
+            let (result, truncated) =
+                methods::list_records(client, table_name, params, filters, identity, state.clone())
+                    .await?;
 
-    Ok(Json(result))
+            if truncated {
+                return Ok(([(limits::TRUNCATED_HEADER, "true")], Json(result)).into_response());
+            }
+            Ok(Json(result).into_response())
+        })
+    })
+    .await
 }
 
 #[axum::debug_handler]
 pub async fn insert_record(
     Path(table_name): Path<String>,
+    Extension(identity): Extension<auth::Identity>,
     State(state): State<AppState>,
     Json(data): Json<InsertBody>,
-) -> Result<Json<OptionalJsonMap>> {
-    dbg!((&table_name, &data));
-    let mut client = state.pool.acquire().await?;
+) -> Result<Response> {
+    if let Either::Right(rows) = &data.inner {
+        limits::check_bulk_rows(rows.len(), &state.limits)?;
+    }
+    let timeout = state.statement_timeout(&identity, &table_name);
+
+    cancel::transaction(state, identity, timeout, move |client, identity, state| {
+        Box::pin(async move {
+            match data.inner {
+                Either::Left(data) => {
+                    let result =
+                        methods::insert_record(client, table_name, data, identity, state.clone())
+                            .await?;
+                    Ok(Json(result).into_response())
+                }
+                Either::Right(rows) => {
+                    let result =
+                        methods::insert_records(client, table_name, rows, identity, state.clone())
+                            .await?;
+                    Ok(Json(result).into_response())
+                }
+            }
+        })
+    })
+    .await
+}
+
+#[axum::debug_handler]
+pub async fn update_record(
+    Path((table_name, record_id)): Path<(String, String)>,
+    Extension(identity): Extension<auth::Identity>,
+    State(state): State<AppState>,
+    Json(data): Json<JsonMap>,
+) -> Result<Json<Option<OptionalJsonMap>>> {
+    let timeout = state.statement_timeout(&identity, &table_name);
+
+    cancel::transaction(state, identity, timeout, move |client, identity, state| {
+        Box::pin(async move {
+            let result = methods::update_record(
+                client,
+                (table_name, record_id),
+                data,
+                identity,
+                state.clone(),
+            )
+            .await?;
 
-    match data.inner {
-        Either::Left(data) => {
-            let result = methods::insert_record(&mut client, table_name, data).await?;
             Ok(Json(result))
-        }
-        Either::Right(_data) => todo!(),
-    }
+        })
+    })
+    .await
 }
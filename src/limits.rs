@@ -0,0 +1,155 @@
+use hyper::StatusCode;
+use postgrest_query_parser::ast::{Field, Select};
+use postgrest_query_parser::Ast;
+
+use crate::config::LimitsConfig;
+use crate::methods::sql::RowLimit;
+use crate::{MyError, Result};
+
+/// Set to `true` on list responses that were cut off at `default_limit` or `max_rows`.
+pub const TRUNCATED_HEADER: &str = "x-truncated";
+
+/// The row limits of the table, falling back to the global ones.
+pub fn row_limit(limits: &LimitsConfig, table_name: &str) -> RowLimit {
+    let table = limits.tables.get(table_name).copied().unwrap_or_default();
+
+    RowLimit {
+        default_limit: table.default_limit.or(limits.default_limit),
+        max_rows: table.max_rows.or(limits.max_rows),
+    }
+}
+
+/// Checks the parsed query parameters before any sql is generated, answers 400 for too
+/// deeply nested or too many selected fields and for a `limit` above `max_limit`.
+pub fn check_ast(ast: &Ast, limits: &LimitsConfig) -> Result<()> {
+    if let Some(select) = &ast.select {
+        let (depth, fields) = select_size(select);
+        if depth > limits.max_select_depth {
+            return Err(MyError::from(anyhow::anyhow!(
+                "select is nested {depth} levels deep, at most {} are allowed",
+                limits.max_select_depth
+            )));
+        }
+        if fields > limits.max_select_fields {
+            return Err(MyError::from(anyhow::anyhow!(
+                "select has {fields} fields, at most {} are allowed",
+                limits.max_select_fields
+            )));
+        }
+    }
+
+    match (ast.limit, limits.max_limit) {
+        (Some(limit), Some(max_limit)) if limit > max_limit => Err(MyError::from(anyhow::anyhow!(
+            "limit {limit} is above the maximum of {max_limit}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Answers 413 for bulk inserts of more than `max_bulk_rows` rows.
+pub fn check_bulk_rows(rows: usize, limits: &LimitsConfig) -> Result<()> {
+    if rows > limits.max_bulk_rows {
+        return Err(MyError::from(anyhow::anyhow!(
+            "{rows} rows in one insert, at most {} are allowed",
+            limits.max_bulk_rows
+        ))
+        .with_status(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+    Ok(())
+}
+
+/// Nesting depth of the embeddings and the number of fields, counted over every level.
+fn select_size(select: &Select) -> (usize, usize) {
+    let mut depth = 0;
+    let mut fields = 0;
+    for field in &select.fields {
+        fields += 1;
+        if let Field::Nested(_, nested) = field {
+            let (nested_depth, nested_fields) = select_size(nested);
+            depth = depth.max(nested_depth + 1);
+            fields += nested_fields;
+        }
+    }
+    (depth, fields)
+}
+
+#[cfg(test)]
+fn string_to_ast(input: &str) -> Ast {
+    let lexer = postgrest_query_parser::Lexer::new(input.chars());
+    Ast::from_lexer(input, lexer).unwrap()
+}
+
+#[test]
+fn limits_check_ast() {
+    let limits = LimitsConfig {
+        max_select_depth: 1,
+        max_select_fields: 4,
+        max_limit: Some(100),
+        ..LimitsConfig::default()
+    };
+
+    assert!(check_ast(&string_to_ast("select=id,author(name)&limit=100"), &limits).is_ok());
+
+    let error = check_ast(
+        &string_to_ast("select=id,author(name,books(title))"),
+        &limits,
+    )
+    .unwrap_err();
+    assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        error.body().message,
+        "select is nested 2 levels deep, at most 1 are allowed"
+    );
+
+    let error = check_ast(&string_to_ast("select=a,b,c,d,e"), &limits).unwrap_err();
+    assert_eq!(
+        error.body().message,
+        "select has 5 fields, at most 4 are allowed"
+    );
+
+    let error = check_ast(&string_to_ast("limit=101"), &limits).unwrap_err();
+    assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn limits_check_bulk_rows() {
+    let limits = LimitsConfig {
+        max_bulk_rows: 2,
+        ..LimitsConfig::default()
+    };
+
+    assert!(check_bulk_rows(2, &limits).is_ok());
+    assert_eq!(
+        check_bulk_rows(3, &limits).unwrap_err().status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+}
+
+#[test]
+fn limits_row_limit_per_table() {
+    let limits: LimitsConfig = toml::from_str(
+        r#"
+        default_limit = 100
+        max_rows = 1000
+
+        [tables.accounts]
+        max_rows = 10
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        row_limit(&limits, "accounts"),
+        RowLimit {
+            default_limit: Some(100),
+            max_rows: Some(10),
+        }
+    );
+    assert_eq!(
+        row_limit(&limits, "books"),
+        RowLimit {
+            default_limit: Some(100),
+            max_rows: Some(1000),
+        }
+    );
+}
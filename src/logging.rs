@@ -0,0 +1,106 @@
+use axum::http::Request;
+use opentelemetry::sdk::trace::Tracer;
+use tracing::Span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::{LogConfig, LogFormat};
+use crate::telemetry;
+use crate::{JsonMap, Value};
+
+const REDACTED: &str = "[redacted]";
+
+/// Installs the global subscriber, `config.level` accepts `RUST_LOG` style directives.
+/// Spans are also exported when an OTLP `tracer` is given.
+pub fn init(config: &LogConfig, tracer: Option<Tracer>) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(&config.level)?;
+    let fmt_layer = match config.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .boxed(),
+    };
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otel_layer)
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Span for every request, `x-request-id` is set by the `SetRequestIdLayer` in front of it.
+/// Only the path is recorded, filters in the query string can hold redacted values.
+pub fn make_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = %request_id,
+    );
+    telemetry::set_parent(&span, request.headers());
+
+    span
+}
+
+/// Copy of `data` that is safe to log, `redact_columns` contains either column names
+/// or `table.column`.
+pub fn redacted(table_name: &str, data: &JsonMap, redact_columns: &[String]) -> JsonMap {
+    data.iter()
+        .map(|(column, value)| {
+            if is_redacted(table_name, column, redact_columns) {
+                (column.clone(), Value::String(String::from(REDACTED)))
+            } else {
+                (column.clone(), value.clone())
+            }
+        })
+        .collect()
+}
+
+fn is_redacted(table_name: &str, column: &str, redact_columns: &[String]) -> bool {
+    redact_columns
+        .iter()
+        .any(|redacted| match redacted.split_once('.') {
+            Some((table, redacted_column)) => table == table_name && redacted_column == column,
+            None => redacted == column,
+        })
+}
+
+#[test]
+fn redacted_replaces_sensitive_columns() {
+    let data: JsonMap = [
+        (String::from("username"), Value::String(String::from("hoi"))),
+        (
+            String::from("password"),
+            Value::String(String::from("secret")),
+        ),
+        (
+            String::from("email"),
+            Value::String(String::from("hoi@example.com")),
+        ),
+    ]
+    .into_iter()
+    .collect();
+    let redact_columns = vec![String::from("password"), String::from("accounts.email")];
+
+    let accounts = redacted("accounts", &data, &redact_columns);
+    assert_eq!(accounts["username"], Value::String(String::from("hoi")));
+    assert_eq!(accounts["password"], Value::String(String::from(REDACTED)));
+    assert_eq!(accounts["email"], Value::String(String::from(REDACTED)));
+
+    let users = redacted("users", &data, &redact_columns);
+    assert_eq!(
+        users["email"],
+        Value::String(String::from("hoi@example.com"))
+    );
+}
@@ -3,58 +3,135 @@ use futures_util::stream::StreamExt;
 use postgres_types::BorrowToSql;
 use postgrest_query_parser::Ast;
 use sea_query_binder::SqlxValues;
-use sea_schema::sea_query::Values;
+use sea_schema::postgres::def::{ColumnInfo, ColumnType, TableDef};
+use sea_schema::sea_query::{self, Values};
 use sqlx_core::connection::Connection;
 use sqlx_core::database::Database;
 use sqlx_core::from_row::FromRow;
 use sqlx_core::pool::PoolConnection;
 use sqlx_core::postgres::PgConnection;
+use sqlx_core::row::Row;
 use sqlx_core::statement::Statement;
 use sqlx_core::{executor::Executor, postgres::Postgres};
 use time::{OffsetDateTime, PrimitiveDateTime};
 use tokio_postgres::{Column, Row};
 
 pub mod sql;
+use crate::auth::Identity;
+use crate::json_schema::Operation;
+use crate::limits;
+use crate::logging;
+use crate::monitoring::timed;
+use crate::policy::{Action, TableAccess};
+use crate::schema;
+use crate::value::coerce::{coerce, coerce_str, placeholder};
 use crate::value::OptionalJsonMapWrapper;
 use crate::{AppState, JsonMap, MyError, OptionalJsonMap, Result, Value};
 
 pub async fn get_record(
     client: &mut PgConnection,
     (table_name, record_id): (String, String),
-    _state: AppState,
+    identity: &Identity,
+    state: AppState,
 ) -> Result<Option<OptionalJsonMap>> {
     // let client = state.pool.get().await?;
 
-    // dbg!(serde_json::from_str::<Value>(&record_id));
-    // let record_id: Value = serde_json::from_str(&record_id).unwrap_or(Value::String(record_id));
-    let record_id = Value::parse_str(&record_id);
+    let table = state.schema.table(&table_name)?;
+    let access = table_access(&state, identity, &table_name, Action::Read)?;
+    let primary_key = schema::primary_key(table)?;
+    let record_id = coerce_str(primary_key, &record_id)?;
 
-    dbg!((&table_name, &record_id));
-
-    let statement = format!("select * from {table_name} where id = $1");
+    let statement = format!(
+        "select {} from {table_name} where {} = {}",
+        state.schema.select_list(table, &access),
+        primary_key.name,
+        placeholder(primary_key, &record_id, 1)
+    );
     let statement = client.prepare(&statement).await?;
-    // let params = SqlxValues(Values(vec![(&record_id).into()]));
-    let params = SqlxValues(Values(vec![]));
+    let params = SqlxValues(Values(vec![record_id]));
     let query = statement.query_with(params);
 
-    let result = match client.fetch_optional(query).await {
+    let result = match timed(
+        &table_name,
+        "select",
+        statement.sql(),
+        client.fetch_optional(query),
+    )
+    .await
+    {
         Ok(Some(record)) => Some(OptionalJsonMapWrapper::from_row(&record)?.0),
         Ok(None) => None,
         Err(e) => return Err(e.into()),
     };
     // let data: Vec<_> = result.into_iter().map(row_to_object).collect();
-    dbg!(&result);
 
     Ok(result)
 }
 
+pub async fn get_record_bytes(
+    client: &mut PgConnection,
+    (table_name, record_id): (String, String),
+    column_name: String,
+    identity: &Identity,
+    state: AppState,
+) -> Result<Option<Vec<u8>>> {
+    let table = state.schema.table(&table_name)?;
+    let column = schema::column(table, &column_name)?;
+    table_access(&state, identity, &table_name, Action::Read)?.check_readable(&column.name)?;
+    if column.col_type != ColumnType::Bytea {
+        return Err(MyError::from(anyhow::anyhow!(
+            "column {column_name} is not a bytea column"
+        )));
+    }
+    let primary_key = schema::primary_key(table)?;
+    let record_id = coerce_str(primary_key, &record_id)?;
+
+    let statement = format!(
+        "select {} from {table_name} where {} = {}",
+        column.name,
+        primary_key.name,
+        placeholder(primary_key, &record_id, 1)
+    );
+    let statement = client.prepare(&statement).await?;
+    let params = SqlxValues(Values(vec![record_id]));
+    let query = statement.query_with(params);
+
+    match timed(
+        &table_name,
+        "select",
+        statement.sql(),
+        client.fetch_optional(query),
+    )
+    .await?
+    {
+        Some(record) => Ok(record.try_get::<Option<Vec<u8>>, _>(0)?),
+        None => Ok(None),
+    }
+}
+
+/// Rows matching the query, with whether they were cut off at the server side row limit.
 pub async fn list_records(
     client: &mut PgConnection,
     table_name: String,
     params: Ast,
-    _state: AppState,
-) -> Result<Vec<OptionalJsonMap>> {
-    let (sql, parameters) = sql::format_params_ast(params, &table_name)?;
+    filters: Vec<(String, String)>,
+    identity: &Identity,
+    state: AppState,
+) -> Result<(Vec<OptionalJsonMap>, bool)> {
+    let table = state.schema.table(&table_name)?;
+    let access = table_access(&state, identity, &table_name, Action::Read)?;
+    let filters: Result<Vec<_>> = filters
+        .iter()
+        .map(|(column, filter)| {
+            access.check_readable(column)?;
+            sql::Filter::parse(table, column, filter)
+        })
+        .collect();
+
+    let row_limit = limits::row_limit(&state.limits, &table_name);
+    let limit = sql::effective_limit(params.limit, row_limit);
+    let (sql, parameters) =
+        sql::format_params_ast(params, &filters?, &state.schema, table, &access, row_limit)?;
     let statement = sql;
     let statement = client.prepare(&statement).await?;
     // let parameters = parameters.iter().map(|x| x.borrow_to_sql());
@@ -62,7 +139,14 @@ pub async fn list_records(
 
     let query = statement.query_with(parameters);
 
-    let result = match client.fetch_all(query).await {
+    let result = match timed(
+        &table_name,
+        "select",
+        statement.sql(),
+        client.fetch_all(query),
+    )
+    .await
+    {
         Ok(record) => record
             .iter()
             .map(OptionalJsonMapWrapper::from_row)
@@ -76,68 +160,222 @@ pub async fn list_records(
             Err(e) => Err(MyError::from(e)),
         })
         .collect();
-    result
+    let mut rows = result?;
+
+    // the extra row selected for a server imposed limit tells whether rows were cut off
+    let truncated = match limit {
+        sql::Limit::Truncated(limit) if rows.len() > limit => {
+            rows.truncate(limit);
+            true
+        }
+        _ => false,
+    };
+
+    Ok((rows, truncated))
+}
+
+/// Checks the policies allow the action, the returned access lists the columns that may not
+/// be selected or written.
+fn table_access(
+    state: &AppState,
+    identity: &Identity,
+    table_name: &str,
+    action: Action,
+) -> Result<TableAccess> {
+    identity.check_table(table_name)?;
+    state
+        .policies
+        .table_access(identity.role.as_deref(), table_name, action)
+}
+
+/// Columns returned after a write, hashed columns are never echoed back.
+fn returning_access(state: &AppState, mut access: TableAccess) -> TableAccess {
+    access
+        .hidden_columns
+        .extend(state.transforms.hashed_columns(&access.table_name));
+    access
+}
+
+/// Looks up the columns for the keys of `data` and coerces the values to the column types.
+fn coerce_data<'a>(
+    table: &'a TableDef,
+    data: JsonMap,
+) -> Result<Vec<(&'a ColumnInfo, sea_query::Value)>> {
+    let mut data: Vec<_> = data.into_iter().collect();
+    data.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    data.into_iter()
+        .map(|(key, value)| {
+            let column = schema::column(table, &key)?;
+            Ok((column, coerce(column, value)?))
+        })
+        .collect()
 }
 
 pub async fn insert_record(
     client: &mut PgConnection,
     table_name: String,
     data: JsonMap,
+    identity: &Identity,
+    state: AppState,
 ) -> Result<OptionalJsonMap> {
-    let mut columns: Vec<_> = data.keys().collect();
-    columns.sort_unstable();
-
-    let values: Vec<_> = columns
-        .iter()
-        .map(|key| data.get(*key).expect("is a valid key").clone().into())
-        .collect();
+    let table = state.schema.table(&table_name)?;
+    let access = table_access(&state, identity, &table_name, Action::Insert)?;
+    for column_name in data.keys() {
+        access.check_writable(column_name)?;
+    }
+    tracing::debug!(
+        table = %table_name,
+        data = ?logging::redacted(&table_name, &data, &state.redact_columns),
+        "inserting record"
+    );
+    let data = state.transforms.normalise(table, data, Operation::Insert);
+    // the json schema checks lengths, required columns and check constraints before the
+    // slow hashing, type mismatches are reported by coerce
+    let body = serde_json::to_value(&data)?;
+    state
+        .validators
+        .validate(&table_name, Operation::Insert, &body)?;
+    let data = state.transforms.hash(&table_name, data).await?;
+    let (columns, values): (Vec<_>, Vec<_>) = coerce_data(table, data)?.into_iter().unzip();
 
     let columns_text = columns
         .iter()
-        .copied()
-        .cloned()
+        .map(|column| column.name.as_str())
         .collect::<Vec<_>>()
         .join(", ");
     let values_placeholders = columns
         .iter()
+        .zip(&values)
         .enumerate()
-        .map(|(mut i, _)| {
-            i += 1;
-            format!("${i}")
-        })
+        .map(|(i, (column, value))| placeholder(column, value, i + 1))
         .collect::<Vec<_>>()
         .join(", ");
+    let returning = state
+        .schema
+        .select_list(table, &returning_access(&state, access));
 
     // let client = state.pool.get().await?;
     let statement = format!(
-        "INSERT INTO {table_name} ({columns_text}) VALUES ({values_placeholders}) RETURNING *"
+        "INSERT INTO {table_name} ({columns_text}) VALUES ({values_placeholders}) RETURNING {returning}"
     );
-    dbg!(&statement);
-
     let statement = client.prepare(&statement).await?;
     let query = statement.query_with(SqlxValues(Values(values)));
 
-    let mut stream = client.fetch_many(query);
+    let data = timed(&table_name, "insert", statement.sql(), async {
+        let mut stream = client.fetch_many(query);
 
-    // let data: Vec<_> = stream.collect().await;
+        // let data: Vec<_> = stream.collect().await;
 
-    let mut data = None;
-    while let Some(item) = stream.next().await {
-        // data.push(row_to_object(item?));
-        // data = Some(OptionalJsonMapWrapper::from_row(item)?);
-        match item? {
-            Left(a) => (),
-            Right(row) => {
-                data = Some(OptionalJsonMapWrapper::from_row(&row)?);
+        let mut data = None;
+        while let Some(item) = stream.next().await {
+            // data.push(row_to_object(item?));
+            // data = Some(OptionalJsonMapWrapper::from_row(item)?);
+            match item? {
+                Left(a) => (),
+                Right(row) => {
+                    data = Some(OptionalJsonMapWrapper::from_row(&row)?);
+                }
             }
         }
-    }
+        Ok::<_, MyError>(data)
+    })
+    .await?;
 
     let data = data.ok_or_else(|| anyhow::Error::msg("invalid return statement"))?;
 
     Ok(data.0)
 }
 
+/// Inserts the rows of a bulk insert in the transaction of the request, one statement per
+/// row since rows may set different columns. A failing row rolls back every row.
+pub async fn insert_records(
+    client: &mut PgConnection,
+    table_name: String,
+    rows: Vec<JsonMap>,
+    identity: &Identity,
+    state: AppState,
+) -> Result<Vec<OptionalJsonMap>> {
+    let mut inserted = Vec::with_capacity(rows.len());
+    for data in rows {
+        let row = insert_record(
+            &mut *client,
+            table_name.clone(),
+            data,
+            identity,
+            state.clone(),
+        )
+        .await?;
+        inserted.push(row);
+    }
+
+    Ok(inserted)
+}
+
+pub async fn update_record(
+    client: &mut PgConnection,
+    (table_name, record_id): (String, String),
+    data: JsonMap,
+    identity: &Identity,
+    state: AppState,
+) -> Result<Option<OptionalJsonMap>> {
+    let table = state.schema.table(&table_name)?;
+    let access = table_access(&state, identity, &table_name, Action::Update)?;
+    for column_name in data.keys() {
+        access.check_writable(column_name)?;
+    }
+    let primary_key = schema::primary_key(table)?;
+    let record_id = coerce_str(primary_key, &record_id)?;
+    let data = state.transforms.normalise(table, data, Operation::Update);
+    let body = serde_json::to_value(&data)?;
+    state
+        .validators
+        .validate(&table_name, Operation::Update, &body)?;
+    let data = state.transforms.hash(&table_name, data).await?;
+    let (columns, mut values): (Vec<_>, Vec<_>) = coerce_data(table, data)?.into_iter().unzip();
+
+    if columns.is_empty() {
+        return Err(MyError::from(anyhow::anyhow!("no columns to update")));
+    }
+
+    let assignments = columns
+        .iter()
+        .zip(&values)
+        .enumerate()
+        .map(|(i, (column, value))| {
+            format!("{} = {}", column.name, placeholder(column, value, i + 1))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let record_id_placeholder = placeholder(primary_key, &record_id, values.len() + 1);
+    values.push(record_id);
+
+    let statement = format!(
+        "UPDATE {table_name} SET {assignments} WHERE {} = {record_id_placeholder} RETURNING {}",
+        primary_key.name,
+        state
+            .schema
+            .select_list(table, &returning_access(&state, access))
+    );
+    let statement = client.prepare(&statement).await?;
+    let query = statement.query_with(SqlxValues(Values(values)));
+
+    let result = match timed(
+        &table_name,
+        "update",
+        statement.sql(),
+        client.fetch_optional(query),
+    )
+    .await
+    {
+        Ok(Some(record)) => Some(OptionalJsonMapWrapper::from_row(&record)?.0),
+        Ok(None) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(result)
+}
+
 // fn try_row_to_object<R: sqlx_core::row::Row<Database = Postgres>>(
 //     row: std::result::Result<R, tokio_postgres::Error>,
 // ) -> Result<OptionalJsonMap>
@@ -1,35 +1,287 @@
 use std::collections::VecDeque;
 
+use crate::policy::TableAccess;
+use crate::schema::{self, Schema};
+use crate::value::coerce::{coerce_str, placeholder};
 use crate::{MyError, Result};
 use postgres_types::ToSql;
 use postgrest_query_parser::ast::order::{self, OrderItem};
 use postgrest_query_parser::ast::{select, Field, FieldKey, Order, Select};
 use postgrest_query_parser::Ast;
 use sea_query_binder::SqlxValues;
+use sea_schema::postgres::def::{ColumnInfo, TableDef};
 use sea_schema::sea_query::{Value, Values};
+use serde::{Deserialize, Serialize};
 
-pub fn format_params_ast(ast: Ast, table_name: &str) -> Result<(String, SqlxValues)> {
+/// Query parameters that are handled by the postgrest query parser, all other
+/// parameters are column filters.
+const RESERVED_PARAMS: [&str; 4] = ["select", "order", "limit", "offset"];
+
+pub fn format_params_ast(
+    ast: Ast,
+    filters: &[Filter],
+    schema: &Schema,
+    table: &TableDef,
+    access: &TableAccess,
+    row_limit: RowLimit,
+) -> Result<(String, SqlxValues)> {
     // ) -> Result<(String, Vec<Box<dyn ToSql + Sync + Send>>)> {
-    dbg!(&ast);
+    tracing::debug!(?ast, "formatting query");
 
-    let select = format_select(ast.select.as_ref(), None)?;
+    let table_name = &table.info.name;
+    let mut parameters = Vec::new();
+    let select = match ast.select {
+        Some(_) => format_select(ast.select.as_ref(), schema, table, access)?,
+        None => schema.select_list(table, access),
+    };
     let join_part = format_join(ast.select.as_ref())?;
-    let order = format_order(&ast.order)?;
-    let limit = format_limit(&ast.limit)?;
+    let where_part = format_where(filters, &mut parameters)?;
+    let order = format_order(&ast.order, access)?;
+    let limit = format_limit(effective_limit(ast.limit, row_limit))?;
     let offset = format_offset(&ast.offset)?;
 
-    Ok(dbg!(
-        format!("SELECT {select} FROM {table_name}{join_part}{order}{limit}{offset}"),
-        SqlxValues(Values(Vec::new()))
-    ))
+    let sql =
+        format!("SELECT {select} FROM {table_name}{join_part}{where_part}{order}{limit}{offset}");
+    tracing::debug!(%sql);
+
+    Ok((sql, SqlxValues(Values(parameters))))
+}
+
+/// Splits the raw query string in the part for the postgrest query parser and the
+/// (url decoded) column filters.
+pub fn split_params(params: &str) -> Result<(String, Vec<(String, String)>)> {
+    let (ast_params, filter_params): (Vec<_>, Vec<_>) = params
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .partition(|param| {
+            let key = param.split_once('=').map(|(key, _)| key).unwrap_or(param);
+            RESERVED_PARAMS.contains(&key)
+        });
+
+    let filters = serde_urlencoded::from_str(&filter_params.join("&"))?;
+
+    Ok((ast_params.join("&"), filters))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IsValue {
+    Null,
+    True,
+    False,
 }
 
-pub fn format_select(select: Option<&Select>, nested: Option<&str>) -> Result<String> {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterOperator {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    Ilike,
+    In,
+    Is(IsValue),
+    /// Intersects the bounding box `min_x,min_y,max_x,max_y` (in WGS 84)
+    Bbox,
+    /// Strictly left of (`<<`)
+    Sl,
+    /// Strictly right of (`>>`)
+    Sr,
+    /// Does not extend to the right of (`&<`)
+    Nxr,
+    /// Does not extend to the left of (`&>`)
+    Nxl,
+    /// Is adjacent to (`-|-`)
+    Adj,
+    /// Overlaps (`&&`)
+    Ov,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub column: ColumnInfo,
+    pub negate: bool,
+    pub operator: FilterOperator,
+    pub values: Vec<Value>,
+}
+
+impl Filter {
+    /// Parses a postgrest filter like `age=not.gte.18`, the values are coerced
+    /// to the type of the column.
+    pub fn parse(table: &TableDef, column_name: &str, input: &str) -> Result<Filter> {
+        let column = schema::column(table, column_name)?.clone();
+
+        let (negate, input) = match input.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, input),
+        };
+
+        let Some((operator, value)) = input.split_once('.') else {
+            return Err(MyError::from(anyhow::anyhow!(
+                "invalid filter '{input}' on column {column_name}"
+            )));
+        };
+
+        let (operator, values) = match operator {
+            "eq" => (FilterOperator::Eq, vec![coerce_str(&column, value)?]),
+            "neq" => (FilterOperator::Neq, vec![coerce_str(&column, value)?]),
+            "gt" => (FilterOperator::Gt, vec![coerce_str(&column, value)?]),
+            "gte" => (FilterOperator::Gte, vec![coerce_str(&column, value)?]),
+            "lt" => (FilterOperator::Lt, vec![coerce_str(&column, value)?]),
+            "lte" => (FilterOperator::Lte, vec![coerce_str(&column, value)?]),
+            "sl" => (FilterOperator::Sl, vec![coerce_str(&column, value)?]),
+            "sr" => (FilterOperator::Sr, vec![coerce_str(&column, value)?]),
+            "nxr" => (FilterOperator::Nxr, vec![coerce_str(&column, value)?]),
+            "nxl" => (FilterOperator::Nxl, vec![coerce_str(&column, value)?]),
+            "adj" => (FilterOperator::Adj, vec![coerce_str(&column, value)?]),
+            "ov" => (FilterOperator::Ov, vec![coerce_str(&column, value)?]),
+            "like" => (FilterOperator::Like, vec![like_pattern(value)]),
+            "ilike" => (FilterOperator::Ilike, vec![like_pattern(value)]),
+            "in" => {
+                let list = value
+                    .strip_prefix('(')
+                    .and_then(|value| value.strip_suffix(')'))
+                    .ok_or_else(|| {
+                        MyError::from(anyhow::anyhow!(
+                            "in filter on column {column_name} expects a list like (1,2,3)"
+                        ))
+                    })?;
+                let values: Result<Vec<_>> = list
+                    .split(',')
+                    .map(|item| coerce_str(&column, item.trim()))
+                    .collect();
+                (FilterOperator::In, values?)
+            }
+            "is" => {
+                let is_value = match value {
+                    "null" => IsValue::Null,
+                    "true" => IsValue::True,
+                    "false" => IsValue::False,
+                    _ => {
+                        return Err(MyError::from(anyhow::anyhow!(
+                            "is filter on column {column_name} expects null, true or false"
+                        )))
+                    }
+                };
+                (FilterOperator::Is(is_value), Vec::new())
+            }
+            "bbox" if schema::is_spatial(&column) => {
+                let coordinates: std::result::Result<Vec<f64>, _> = value
+                    .trim_start_matches('(')
+                    .trim_end_matches(')')
+                    .split(',')
+                    .map(|item| item.trim().parse::<f64>())
+                    .collect();
+                match coordinates {
+                    Ok(coordinates) if coordinates.len() == 4 => (
+                        FilterOperator::Bbox,
+                        coordinates
+                            .into_iter()
+                            .map(|coordinate| Value::Double(Some(coordinate)))
+                            .collect(),
+                    ),
+                    _ => {
+                        return Err(MyError::from(anyhow::anyhow!(
+                            "bbox filter on column {column_name} expects min_x,min_y,max_x,max_y"
+                        )))
+                    }
+                }
+            }
+            "bbox" => {
+                return Err(MyError::from(anyhow::anyhow!(
+                    "bbox filter needs a geometry or geography column, {column_name} is not"
+                )))
+            }
+            _ => {
+                return Err(MyError::from(anyhow::anyhow!(
+                    "unknown filter operator '{operator}'"
+                )))
+            }
+        };
+
+        Ok(Filter {
+            column,
+            negate,
+            operator,
+            values,
+        })
+    }
+}
+
+fn like_pattern(value: &str) -> Value {
+    Value::String(Some(Box::new(value.replace('*', "%"))))
+}
+
+pub fn format_where(filters: &[Filter], parameters: &mut Vec<Value>) -> Result<String> {
+    if filters.is_empty() {
+        return Ok(String::new());
+    }
+
+    let conditions: Result<Vec<_>> = filters
+        .iter()
+        .map(|filter| format_filter(filter, parameters))
+        .collect();
+
+    Ok(format!(" WHERE {}", conditions?.join(" AND ")))
+}
+
+fn format_filter(filter: &Filter, parameters: &mut Vec<Value>) -> Result<String> {
+    let column = &filter.column;
+    let mut placeholders = Vec::new();
+    let mut plain_placeholders = Vec::new();
+    for value in &filter.values {
+        parameters.push(value.clone());
+        placeholders.push(placeholder(column, value, parameters.len()));
+        plain_placeholders.push(format!("${}", parameters.len()));
+    }
+
+    let condition = match filter.operator {
+        FilterOperator::Eq => format!("{} = {}", column.name, placeholders[0]),
+        FilterOperator::Neq => format!("{} <> {}", column.name, placeholders[0]),
+        FilterOperator::Gt => format!("{} > {}", column.name, placeholders[0]),
+        FilterOperator::Gte => format!("{} >= {}", column.name, placeholders[0]),
+        FilterOperator::Lt => format!("{} < {}", column.name, placeholders[0]),
+        FilterOperator::Lte => format!("{} <= {}", column.name, placeholders[0]),
+        FilterOperator::Like => format!("{} LIKE {}", column.name, plain_placeholders[0]),
+        FilterOperator::Ilike => format!("{} ILIKE {}", column.name, plain_placeholders[0]),
+        FilterOperator::In => format!("{} IN ({})", column.name, placeholders.join(", ")),
+        FilterOperator::Is(IsValue::Null) => format!("{} IS NULL", column.name),
+        FilterOperator::Is(IsValue::True) => format!("{} IS TRUE", column.name),
+        FilterOperator::Is(IsValue::False) => format!("{} IS FALSE", column.name),
+        FilterOperator::Sl => format!("{} << {}", column.name, placeholders[0]),
+        FilterOperator::Sr => format!("{} >> {}", column.name, placeholders[0]),
+        FilterOperator::Nxr => format!("{} &< {}", column.name, placeholders[0]),
+        FilterOperator::Nxl => format!("{} &> {}", column.name, placeholders[0]),
+        FilterOperator::Adj => format!("{} -|- {}", column.name, placeholders[0]),
+        FilterOperator::Ov => format!("{} && {}", column.name, placeholders[0]),
+        FilterOperator::Bbox => format!(
+            "{} && ST_MakeEnvelope({}, 4326)",
+            column.name,
+            plain_placeholders.join(", ")
+        ),
+    };
+
+    if filter.negate {
+        Ok(format!("NOT ({condition})"))
+    } else {
+        Ok(condition)
+    }
+}
+
+/// Columns of the `select` parameter, converted to json like the default select list.
+/// Hidden columns of the table are answered with a 403.
+pub fn format_select(
+    select: Option<&Select>,
+    schema: &Schema,
+    table: &TableDef,
+    access: &TableAccess,
+) -> Result<String> {
     if let Some(select) = select {
         let formatted_fields: Result<Vec<_>> = select
             .fields
             .iter()
-            .map(|field| format_select_field(field, nested))
+            .map(|field| format_select_field(field, schema, table, access))
             .collect();
         Ok(formatted_fields?.join(", "))
     } else {
@@ -37,13 +289,20 @@ pub fn format_select(select: Option<&Select>, nested: Option<&str>) -> Result<St
     }
 }
 
-fn format_select_field(field: &Field, nested: Option<&str>) -> Result<String> {
+fn format_select_field(
+    field: &Field,
+    schema: &Schema,
+    table: &TableDef,
+    access: &TableAccess,
+) -> Result<String> {
     match field {
-        Field::Key(key) => format_field_key(key, nested),
-        Field::Nested(key, nested_field) => {
-            let out = format_field_key(key, nested)?;
-            format_select(Some(nested_field), Some(&out))
-        }
+        Field::Key(key) => format_field_key(key, schema, table, access),
+        // without joins the embedded columns would be read from this table, past its
+        // hidden columns
+        Field::Nested(key, _) => Err(MyError::from(anyhow::anyhow!(
+            "embedding {} is not supported yet",
+            key.column
+        ))),
         _ => {
             return Err(MyError::from(anyhow::anyhow!(
                 "this select is not supported yet"
@@ -52,22 +311,26 @@ fn format_select_field(field: &Field, nested: Option<&str>) -> Result<String> {
     }
 }
 
-fn format_field_key(key: &FieldKey, nested: Option<&str>) -> Result<String> {
-    let mut column = key.column.to_string();
-    if let Some(nested) = nested {
-        column = format!("{nested}.{column}")
-    }
-
-    if let Some(alias) = &key.alias {
-        column.push_str(" as ");
-        if let Some(nested) = nested {
-            column.push_str(&format!("{nested}.{alias}"))
-        } else {
-            column.push_str(&alias);
+fn format_field_key(
+    key: &FieldKey,
+    schema: &Schema,
+    table: &TableDef,
+    access: &TableAccess,
+) -> Result<String> {
+    let column = key.column.to_string();
+    if column == "*" {
+        if !access.hidden_columns.is_empty() {
+            return Err(MyError::from(anyhow::anyhow!(
+                "select=* includes hidden columns of {}, list the columns instead",
+                access.table_name
+            ))
+            .with_status(hyper::StatusCode::FORBIDDEN));
         }
-    };
+        return Ok(schema.select_list(table, access));
+    }
 
-    Ok(column)
+    access.check_readable(&column)?;
+    schema.select_column(table, &column, key.alias.as_deref())
 }
 
 pub fn format_join(select: Option<&Select>) -> Result<String> {
@@ -108,10 +371,14 @@ fn inner_collect_paths(field: &Field, paths: &mut Vec<VecDeque<String>>) -> Resu
     }
 }
 
-pub fn format_order(order: &Option<Order>) -> Result<String> {
+/// Hidden columns cannot be ordered by either, the order of the rows would leak them.
+pub fn format_order(order: &Option<Order>, access: &TableAccess) -> Result<String> {
     if let Some(order) = order {
-        let formatted_fields: Result<Vec<_>> =
-            order.fields.iter().map(format_order_field).collect();
+        let formatted_fields: Result<Vec<_>> = order
+            .fields
+            .iter()
+            .map(|field| format_order_field(field, access))
+            .collect();
         let order_fields = formatted_fields?.join(", ");
         Ok(format!(" ORDER BY {order_fields}"))
     } else {
@@ -119,8 +386,9 @@ pub fn format_order(order: &Option<Order>) -> Result<String> {
     }
 }
 
-fn format_order_field(field: &OrderItem) -> Result<String> {
+fn format_order_field(field: &OrderItem, access: &TableAccess) -> Result<String> {
     let mut ordering = field.field.to_string();
+    access.check_readable(&ordering)?;
     match field.operator {
         order::Operator::Asc => ordering.push_str(" ASC"),
         order::Operator::Desc => ordering.push_str(" DESC"),
@@ -135,10 +403,43 @@ fn format_order_field(field: &OrderItem) -> Result<String> {
     Ok(ordering)
 }
 
-fn format_limit(limit: &Option<usize>) -> Result<String> {
+/// Server side bounds on the rows of a list request, from the global and per table limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RowLimit {
+    /// Used when the request has no `limit`
+    pub default_limit: Option<usize>,
+    /// Caps the `limit` of the request
+    pub max_rows: Option<usize>,
+}
+
+/// The limit a list query runs with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Unlimited,
+    /// Asked for by the client, within the bounds
+    Requested(usize),
+    /// Imposed by the server, one more row is selected to tell whether rows were cut off
+    Truncated(usize),
+}
+
+pub fn effective_limit(requested: Option<usize>, row_limit: RowLimit) -> Limit {
+    match (requested, row_limit.max_rows) {
+        (Some(requested), Some(max_rows)) if requested > max_rows => Limit::Truncated(max_rows),
+        (Some(requested), _) => Limit::Requested(requested),
+        (None, max_rows) => match (row_limit.default_limit, max_rows) {
+            (Some(default_limit), Some(max_rows)) => Limit::Truncated(default_limit.min(max_rows)),
+            (Some(limit), None) | (None, Some(limit)) => Limit::Truncated(limit),
+            (None, None) => Limit::Unlimited,
+        },
+    }
+}
+
+fn format_limit(limit: Limit) -> Result<String> {
     match limit {
-        Some(limit) => Ok(format!(" LIMIT {limit}")),
-        None => Ok(String::new()),
+        Limit::Unlimited => Ok(String::new()),
+        Limit::Requested(limit) => Ok(format!(" LIMIT {limit}")),
+        Limit::Truncated(limit) => Ok(format!(" LIMIT {}", limit + 1)),
     }
 }
 fn format_offset(offset: &Option<usize>) -> Result<String> {
@@ -154,28 +455,112 @@ fn string_to_ast(input: &str) -> Ast {
     postgrest_query_parser::Ast::from_lexer(input, lexer).unwrap()
 }
 
+#[cfg(test)]
+fn testing_table(name: &str, columns: &[(&str, sea_schema::postgres::def::Type)]) -> TableDef {
+    use sea_schema::postgres::def::TableInfo;
+
+    TableDef {
+        info: TableInfo {
+            name: name.to_string(),
+            of_type: None,
+        },
+        columns: columns
+            .iter()
+            .map(|(name, col_type)| ColumnInfo {
+                name: name.to_string(),
+                col_type: col_type.clone(),
+                default: None,
+                generated: None,
+                not_null: None,
+                is_identity: false,
+            })
+            .collect(),
+        check_constraints: Vec::new(),
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    }
+}
+
 #[test]
 fn select_format_sql() {
+    let table = testing_table(
+        "testing",
+        &[
+            ("id", sea_schema::postgres::def::Type::Integer),
+            ("artist", sea_schema::postgres::def::Type::Text),
+        ],
+    );
     let input = "select=id,my_artist:artist";
-    let (sql, args) = format_params_ast(string_to_ast(input), "testing").unwrap();
+    let (sql, args) = format_params_ast(
+        string_to_ast(input),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
 
     assert_eq!("SELECT id, artist as my_artist FROM testing", sql);
-    assert!(args.0 .0.is_empty())
+    assert!(args.0 .0.is_empty());
+
+    let error = format_params_ast(
+        string_to_ast("select=id,painter"),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.status(), hyper::StatusCode::BAD_REQUEST);
 }
 
 #[test]
 fn select_with_nested_format_sql() {
+    let table = testing_table(
+        "testing",
+        &[
+            ("id", sea_schema::postgres::def::Type::Integer),
+            ("artist", sea_schema::postgres::def::Type::Text),
+        ],
+    );
     let input = "select=id,projects(id)";
-    let (sql, args) = format_params_ast(string_to_ast(input), "testing").unwrap();
+    let error = format_params_ast(
+        string_to_ast(input),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap_err();
 
-    assert_eq!("SELECT id, artist as my_artist FROM testing", sql);
-    assert!(args.0 .0.is_empty())
+    assert_eq!(error.status(), hyper::StatusCode::BAD_REQUEST);
 }
 
 #[test]
 fn order_by_format_sql() {
+    let table = testing_table(
+        "testing",
+        &[
+            ("id", sea_schema::postgres::def::Type::Integer),
+            ("artist", sea_schema::postgres::def::Type::Text),
+        ],
+    );
     let input = "select=id,artist&order=title.desc,width.asc.nullsfirst,id.desc.nullslast";
-    let (sql, args) = format_params_ast(string_to_ast(input), "testing").unwrap();
+    let (sql, args) = format_params_ast(
+        string_to_ast(input),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
 
     assert_eq!(
         "SELECT id, artist FROM testing ORDER BY title DESC, width ASC NULLS FIRST, id DESC NULLS LAST",
@@ -186,9 +571,297 @@ fn order_by_format_sql() {
 
 #[test]
 fn limit_and_offset_format_sql() {
+    let table = testing_table(
+        "testing",
+        &[("id", sea_schema::postgres::def::Type::Integer)],
+    );
     let input = "limit=512&offset=9321";
-    let (sql, args) = format_params_ast(string_to_ast(input), "testing").unwrap();
+    let (sql, args) = format_params_ast(
+        string_to_ast(input),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
 
     assert_eq!("SELECT * FROM testing LIMIT 512 OFFSET 9321", sql);
     assert!(args.0 .0.is_empty())
 }
+
+#[test]
+fn where_format_sql() {
+    use sea_schema::postgres::def::{StringAttr, TableInfo};
+
+    let column = |name: &str, col_type| ColumnInfo {
+        name: name.to_string(),
+        col_type,
+        default: None,
+        generated: None,
+        not_null: None,
+        is_identity: false,
+    };
+    let table = TableDef {
+        info: TableInfo {
+            name: String::from("testing"),
+            of_type: None,
+        },
+        columns: vec![
+            column("id", sea_schema::postgres::def::Type::Integer),
+            column(
+                "title",
+                sea_schema::postgres::def::Type::Varchar(StringAttr::default()),
+            ),
+        ],
+        check_constraints: Vec::new(),
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    };
+
+    let filters = vec![
+        Filter::parse(&table, "id", "in.(1,2)").unwrap(),
+        Filter::parse(&table, "title", "not.like.*SQL*").unwrap(),
+    ];
+    let (sql, args) = format_params_ast(
+        string_to_ast("limit=1"),
+        &filters,
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        "SELECT * FROM testing WHERE id IN ($1, $2) AND NOT (title LIKE $3) LIMIT 1",
+        sql
+    );
+    assert_eq!(
+        args.0 .0,
+        vec![
+            Value::Int(Some(1)),
+            Value::Int(Some(2)),
+            Value::String(Some(Box::new("%SQL%".to_string())))
+        ]
+    );
+    assert!(Filter::parse(&table, "id", "eq.abc").is_err());
+}
+
+#[test]
+fn split_params_filters() {
+    let (ast, filters) = split_params("select=id&title=eq.My%20book&limit=1").unwrap();
+
+    assert_eq!("select=id&limit=1", ast);
+    assert_eq!(
+        vec![(String::from("title"), String::from("eq.My book"))],
+        filters
+    );
+}
+
+#[test]
+fn bbox_format_sql() {
+    use sea_schema::postgres::def::TableInfo;
+
+    let table = TableDef {
+        info: TableInfo {
+            name: String::from("locations"),
+            of_type: None,
+        },
+        columns: vec![ColumnInfo {
+            name: String::from("position"),
+            col_type: sea_schema::postgres::def::Type::Unknown(String::from("geometry")),
+            default: None,
+            generated: None,
+            not_null: None,
+            is_identity: false,
+        }],
+        check_constraints: Vec::new(),
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    };
+
+    let filters = vec![Filter::parse(&table, "position", "bbox.(4.7,52.2,5.1,52.5)").unwrap()];
+    let (sql, args) = format_params_ast(
+        string_to_ast("limit=1"),
+        &filters,
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        "SELECT * FROM locations WHERE position && ST_MakeEnvelope($1, $2, $3, $4, 4326) LIMIT 1",
+        sql
+    );
+    assert_eq!(args.0 .0.len(), 4);
+    assert!(Filter::parse(&table, "position", "bbox.1,2,3").is_err());
+
+    let (sql, _) = format_params_ast(
+        string_to_ast("select=location:position"),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        "SELECT ST_AsGeoJSON(position)::jsonb as location FROM locations",
+        sql
+    );
+}
+
+#[test]
+fn range_operators_format_sql() {
+    use sea_schema::postgres::def::TableInfo;
+
+    let table = TableDef {
+        info: TableInfo {
+            name: String::from("bookings"),
+            of_type: None,
+        },
+        columns: vec![ColumnInfo {
+            name: String::from("during"),
+            col_type: sea_schema::postgres::def::Type::TsTzRange,
+            default: None,
+            generated: None,
+            not_null: None,
+            is_identity: false,
+        }],
+        check_constraints: Vec::new(),
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    };
+
+    let filters = vec![
+        Filter::parse(&table, "during", "ov.[2020-01-01,2020-02-01)").unwrap(),
+        Filter::parse(&table, "during", "not.adj.[2020-02-01,2020-03-01)").unwrap(),
+        Filter::parse(&table, "during", "nxr.[2020-01-01,2021-01-01)").unwrap(),
+    ];
+    let (sql, _) = format_params_ast(
+        Ast::default(),
+        &filters,
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        "SELECT * FROM bookings WHERE during && $1::text::tstzrange AND NOT (during -|- $2::text::tstzrange) AND during &< $3::text::tstzrange",
+        sql
+    );
+
+    let (sql, _) = format_params_ast(
+        string_to_ast("select=during"),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        RowLimit::default(),
+    )
+    .unwrap();
+    assert!(sql.starts_with("SELECT CASE WHEN during IS NULL THEN NULL"));
+    assert!(sql.ends_with(" END as during FROM bookings"));
+}
+
+#[test]
+fn select_hidden_column_forbidden() {
+    let table = testing_table(
+        "accounts",
+        &[
+            ("id", sea_schema::postgres::def::Type::Integer),
+            ("username", sea_schema::postgres::def::Type::Text),
+            ("password", sea_schema::postgres::def::Type::Text),
+        ],
+    );
+    let access = TableAccess {
+        table_name: String::from("accounts"),
+        hidden_columns: std::collections::HashSet::from([String::from("password")]),
+        ..TableAccess::default()
+    };
+
+    let error = format_params_ast(
+        string_to_ast("select=id,password"),
+        &[],
+        &Schema::default(),
+        &table,
+        &access,
+        RowLimit::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.status(), hyper::StatusCode::FORBIDDEN);
+
+    let (sql, _) = format_params_ast(
+        string_to_ast("select=id,username"),
+        &[],
+        &Schema::default(),
+        &table,
+        &access,
+        RowLimit::default(),
+    )
+    .unwrap();
+    assert_eq!("SELECT id, username FROM accounts", sql);
+
+    let error = format_params_ast(
+        string_to_ast("select=id,accounts(password)"),
+        &[],
+        &Schema::default(),
+        &table,
+        &access,
+        RowLimit::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.status(), hyper::StatusCode::BAD_REQUEST);
+
+    let error = format_params_ast(
+        string_to_ast("select=id&order=password.asc"),
+        &[],
+        &Schema::default(),
+        &table,
+        &access,
+        RowLimit::default(),
+    )
+    .unwrap_err();
+    assert_eq!(error.status(), hyper::StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn row_limit_format_sql() {
+    let table = testing_table(
+        "testing",
+        &[("id", sea_schema::postgres::def::Type::Integer)],
+    );
+    let row_limit = RowLimit {
+        default_limit: Some(20),
+        max_rows: Some(100),
+    };
+    assert_eq!(effective_limit(None, row_limit), Limit::Truncated(20));
+    assert_eq!(effective_limit(Some(50), row_limit), Limit::Requested(50));
+    assert_eq!(effective_limit(Some(500), row_limit), Limit::Truncated(100));
+    assert_eq!(effective_limit(None, RowLimit::default()), Limit::Unlimited);
+
+    let (sql, _) = format_params_ast(
+        string_to_ast("offset=5"),
+        &[],
+        &Schema::default(),
+        &table,
+        &TableAccess::default(),
+        row_limit,
+    )
+    .unwrap();
+    assert_eq!("SELECT * FROM testing LIMIT 21 OFFSET 5", sql);
+}
@@ -0,0 +1,129 @@
+use std::future::Future;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, State};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::header::CONTENT_TYPE;
+use metrics::{histogram, increment_counter};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::Instrument;
+
+use crate::{pool, AppState};
+
+pub const REQUESTS_COUNTER: &str = "restql_http_requests_total";
+pub const ERRORS_COUNTER: &str = "restql_http_errors_total";
+pub const REQUEST_HISTOGRAM: &str = "restql_http_request_duration_seconds";
+pub const QUERY_HISTOGRAM: &str = "restql_db_query_duration_seconds";
+
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Installs the global Prometheus recorder, the returned handle renders the scrape output.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix(String::from("seconds")), DURATION_BUCKETS)?
+        .install_recorder()
+}
+
+/// Handler for `/metrics`.
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let Some(handle) = &state.metrics else {
+        return hyper::StatusCode::NOT_FOUND.into_response();
+    };
+    pool::record_gauges(&state.pool);
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
+
+/// Middleware counting requests and errors and timing them, labelled by method, table
+/// and status.
+pub async fn track_requests<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let table = table_label(&state, &request);
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("table", table),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    increment_counter!(REQUESTS_COUNTER, &labels);
+    if response.status().is_client_error() || response.status().is_server_error() {
+        increment_counter!(ERRORS_COUNTER, &labels);
+    }
+    histogram!(REQUEST_HISTOGRAM, start.elapsed().as_secs_f64(), &labels);
+
+    response
+}
+
+/// Only known tables are used as label, so random urls cannot blow up the number of series.
+fn table_label<B>(state: &AppState, request: &Request<B>) -> String {
+    let is_table_route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().starts_with("/:table_name"))
+        .unwrap_or(false);
+    if !is_table_route {
+        return String::new();
+    }
+
+    match request
+        .uri()
+        .path()
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+    {
+        Some(table) if state.schema.tables.contains_key(table) => table.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Runs a database query in its own span and records its duration.
+pub async fn timed<F: Future>(
+    table: &str,
+    operation: &'static str,
+    sql: &str,
+    query: F,
+) -> F::Output {
+    let span = tracing::info_span!(
+        "sql",
+        otel.name = %format!("{operation} {table}"),
+        db.system = "postgresql",
+        db.operation = operation,
+        db.sql.table = table,
+        db.statement = sql,
+    );
+
+    let start = Instant::now();
+    let output = query.instrument(span).await;
+    let elapsed = start.elapsed();
+    tracing::debug!(
+        table,
+        operation,
+        sql,
+        elapsed_ms = elapsed.as_millis(),
+        "query finished"
+    );
+    histogram!(
+        QUERY_HISTOGRAM,
+        elapsed.as_secs_f64(),
+        "table" => table.to_string(),
+        "operation" => operation
+    );
+
+    output
+}
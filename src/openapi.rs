@@ -0,0 +1,400 @@
+use sea_schema::postgres::def::{ColumnInfo, ColumnType, TableDef};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::json_schema;
+use crate::schema::{self, Schema};
+
+pub const OPENAPI_CONTENT_TYPE: &str = "application/openapi+json";
+
+/// Integers sent as text, like `"42"`, which `value::coerce` accepts as well.
+const INTEGER_PATTERN: &str = "^[-+]?[0-9]+$";
+
+/// OpenAPI 3.1 document for every introspected table.
+pub fn document(schema: &Schema) -> JsonValue {
+    let mut table_names: Vec<_> = schema.tables.keys().collect();
+    table_names.sort();
+
+    let mut paths = Map::new();
+    let mut schemas = Map::new();
+    for table_name in table_names {
+        let table = &schema.tables[table_name];
+        paths.insert(format!("/{table_name}"), table_path(table));
+        if let Ok(primary_key) = schema::primary_key(table) {
+            paths.insert(
+                format!("/{table_name}/{{{}}}", primary_key.name),
+                record_path(schema, table, primary_key),
+            );
+        }
+        schemas.insert(table_name.clone(), table_schema(schema, table));
+    }
+    schemas.insert(String::from("Error"), error_schema());
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "responses": {
+                "Error": {
+                    "description": "Error, the status is derived from the postgres error code",
+                    "content": {
+                        "application/json": {
+                            "schema": {"$ref": "#/components/schemas/Error"}
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn table_path(table: &TableDef) -> JsonValue {
+    let table_name = &table.info.name;
+    let mut parameters = vec![
+        query_parameter("select", "Columns to return, like `id,name:title`"),
+        query_parameter("order", "Columns to order by, like `author.desc`"),
+        query_parameter("limit", "Maximum number of rows"),
+        query_parameter("offset", "Number of rows to skip"),
+    ];
+    parameters.extend(table.columns.iter().map(|column| {
+        query_parameter(
+            &column.name,
+            "Filter on this column, like `eq.value`, `gt.5` or `in.(1,2)`",
+        )
+    }));
+
+    json!({
+        "get": {
+            "operationId": format!("list_{table_name}"),
+            "tags": [table_name],
+            "parameters": parameters,
+            "responses": {
+                "200": json_response("Matching rows", json!({
+                    "type": "array",
+                    "items": table_ref(table_name),
+                })),
+                "default": {"$ref": "#/components/responses/Error"},
+            }
+        },
+        "post": {
+            "operationId": format!("insert_{table_name}"),
+            "tags": [table_name],
+            "requestBody": {
+                "required": true,
+                "content": {"application/json": {"schema": table_ref(table_name)}},
+            },
+            "responses": {
+                "200": json_response("The inserted row", table_ref(table_name)),
+                "default": {"$ref": "#/components/responses/Error"},
+            }
+        }
+    })
+}
+
+fn record_path(schema: &Schema, table: &TableDef, primary_key: &ColumnInfo) -> JsonValue {
+    let table_name = &table.info.name;
+    let id_parameter = json!({
+        "name": primary_key.name,
+        "in": "path",
+        "required": true,
+        "schema": column_schema(schema, primary_key),
+    });
+    // every column is optional when updating
+    let mut update_schema = table_schema(schema, table);
+    if let Some(object) = update_schema.as_object_mut() {
+        object.remove("required");
+    }
+
+    json!({
+        "get": {
+            "operationId": format!("get_{table_name}"),
+            "tags": [table_name],
+            "parameters": [
+                id_parameter,
+                query_parameter(
+                    "column",
+                    "Bytea column to download raw, with `Accept: application/octet-stream`",
+                ),
+            ],
+            "responses": {
+                "200": json_response("The row, null when it does not exist", table_ref(table_name)),
+                "default": {"$ref": "#/components/responses/Error"},
+            }
+        },
+        "patch": {
+            "operationId": format!("update_{table_name}"),
+            "tags": [table_name],
+            "parameters": [id_parameter],
+            "requestBody": {
+                "required": true,
+                "content": {"application/json": {"schema": update_schema}},
+            },
+            "responses": {
+                "200": json_response("The updated row", table_ref(table_name)),
+                "default": {"$ref": "#/components/responses/Error"},
+            }
+        }
+    })
+}
+
+fn query_parameter(name: &str, description: &str) -> JsonValue {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": {"type": "string"},
+    })
+}
+
+fn json_response(description: &str, schema: JsonValue) -> JsonValue {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": schema}},
+    })
+}
+
+fn table_ref(table_name: &str) -> JsonValue {
+    json!({ "$ref": format!("#/components/schemas/{table_name}") })
+}
+
+/// Object schema of a row, columns without a default that are NOT NULL are required.
+/// Simple CHECK constraints become bounds on the column.
+pub fn table_schema(schema: &Schema, table: &TableDef) -> JsonValue {
+    let mut properties: Map<_, _> = table
+        .columns
+        .iter()
+        .map(|column| (column.name.clone(), column_schema(schema, column)))
+        .collect();
+    for (column_name, keyword, value) in json_schema::check_keywords(table) {
+        if let Some(JsonValue::Object(column_schema)) = properties.get_mut(&column_name) {
+            column_schema.insert(String::from(keyword), value);
+        }
+    }
+    let required: Vec<_> = table
+        .columns
+        .iter()
+        .filter(|column| is_required(column))
+        .map(|column| column.name.clone())
+        .collect();
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+pub fn is_required(column: &ColumnInfo) -> bool {
+    column.not_null.is_some()
+        && column.default.is_none()
+        && column.generated.is_none()
+        && !column.is_identity
+        && !matches!(
+            column.col_type,
+            ColumnType::SmallSerial | ColumnType::Serial | ColumnType::BigSerial
+        )
+}
+
+/// Schema of the json representation of a column, see `value::coerce` for what is accepted.
+pub fn column_schema(schema: &Schema, column: &ColumnInfo) -> JsonValue {
+    let mut column_schema = type_schema(schema, column);
+
+    if column.not_null.is_none() {
+        match column_schema.get_mut("type") {
+            Some(JsonValue::String(type_name)) => {
+                column_schema["type"] = json!([type_name, "null"]);
+            }
+            Some(JsonValue::Array(type_names)) => type_names.push(json!("null")),
+            _ => (),
+        }
+        if let Some(JsonValue::Array(values)) = column_schema.get_mut("enum") {
+            values.push(JsonValue::Null);
+        }
+    }
+    if let Some(default) = column
+        .default
+        .as_ref()
+        .and_then(|default| default_value(&default.0))
+    {
+        column_schema["default"] = default;
+    }
+    if column.generated.is_some() || column.is_identity {
+        column_schema["readOnly"] = json!(true);
+    }
+
+    column_schema
+}
+
+/// Numbers and booleans can be sent as text too, like `value::coerce` parses them.
+fn type_schema(schema: &Schema, column: &ColumnInfo) -> JsonValue {
+    match &column.col_type {
+        ColumnType::SmallInt | ColumnType::SmallSerial => json!({
+            "type": ["integer", "string"],
+            "minimum": i16::MIN,
+            "maximum": i16::MAX,
+            "pattern": INTEGER_PATTERN,
+        }),
+        ColumnType::Integer | ColumnType::Serial => {
+            json!({"type": ["integer", "string"], "format": "int32", "pattern": INTEGER_PATTERN})
+        }
+        ColumnType::BigInt | ColumnType::BigSerial => {
+            json!({"type": ["integer", "string"], "format": "int64", "pattern": INTEGER_PATTERN})
+        }
+        ColumnType::Real => json!({"type": ["number", "string"], "format": "float"}),
+        ColumnType::DoublePrecision => json!({"type": ["number", "string"], "format": "double"}),
+        // sent as text to keep the precision, like `"19.99"`
+        ColumnType::Decimal(_) | ColumnType::Numeric(_) | ColumnType::Money => {
+            json!({"type": ["number", "string"]})
+        }
+        ColumnType::Varchar(attr) | ColumnType::Char(attr) => match attr.length {
+            Some(length) => json!({"type": "string", "maxLength": length}),
+            None => json!({"type": "string"}),
+        },
+        ColumnType::Text => json!({"type": "string"}),
+        ColumnType::Bytea => json!({"type": "string", "contentEncoding": "base64"}),
+        ColumnType::Timestamp(_) | ColumnType::TimestampWithTimeZone(_) => {
+            json!({"type": "string", "format": "date-time"})
+        }
+        ColumnType::Date => json!({"type": "string", "format": "date"}),
+        ColumnType::Time(_) | ColumnType::TimeWithTimeZone(_) => {
+            json!({"type": "string", "format": "time"})
+        }
+        ColumnType::Interval(_) => json!({"type": "string", "format": "duration"}),
+        ColumnType::Boolean => {
+            json!({"type": ["boolean", "string"], "pattern": "^(true|false)$"})
+        }
+        ColumnType::Uuid => json!({"type": "string", "format": "uuid"}),
+        ColumnType::Json | ColumnType::JsonBinary => json!({}),
+        ColumnType::Enum(enum_def) => json!({"type": "string", "enum": enum_def.values}),
+        _ if schema::is_range(column) => json!({
+            "anyOf": [
+                {
+                    "type": "object",
+                    "properties": {
+                        "lower": {},
+                        "upper": {},
+                        "bounds": {"enum": ["[]", "[)", "(]", "()"]},
+                    },
+                },
+                {"type": "string", "description": "Range literal like `[1,5)` or `empty`"},
+            ]
+        }),
+        _ if schema::is_spatial(column) => {
+            json!({"type": "object", "description": "GeoJSON geometry"})
+        }
+        _ if schema.is_composite(column) => json!({"type": "object"}),
+        // arrays and other types postgres casts from text, anything goes
+        _ => json!({}),
+    }
+}
+
+/// Json value of a literal default expression like `'pending'::order_status`, `5` or
+/// `true`. Function calls like `now()` have no json value.
+fn default_value(expression: &str) -> Option<JsonValue> {
+    let literal = match expression.rsplit_once("::") {
+        Some((literal, _cast)) => literal,
+        None => expression,
+    };
+
+    if let Some(text) = literal
+        .strip_prefix('\'')
+        .and_then(|literal| literal.strip_suffix('\''))
+    {
+        return Some(JsonValue::String(text.replace("''", "'")));
+    }
+    match literal {
+        "true" => return Some(JsonValue::Bool(true)),
+        "false" => return Some(JsonValue::Bool(false)),
+        _ => (),
+    }
+    if let Ok(number) = literal.parse::<i64>() {
+        return Some(json!(number));
+    }
+    literal.parse::<f64>().ok().map(|number| json!(number))
+}
+
+fn error_schema() -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": {
+            "code": {"type": ["string", "null"], "description": "Postgres SQLSTATE"},
+            "message": {"type": "string"},
+            "details": {"type": ["string", "null"]},
+            "hint": {"type": ["string", "null"]},
+        },
+        "required": ["code", "message", "details", "hint"],
+    })
+}
+
+#[test]
+fn openapi_default_value() {
+    assert_eq!(
+        default_value("'pending'::order_status"),
+        Some(json!("pending"))
+    );
+    assert_eq!(default_value("'it''s'::text"), Some(json!("it's")));
+    assert_eq!(default_value("true"), Some(json!(true)));
+    assert_eq!(default_value("5"), Some(json!(5)));
+    assert_eq!(default_value("now()"), None);
+    assert_eq!(default_value("nextval('orders_id_seq'::regclass)"), None);
+}
+
+#[test]
+fn openapi_column_schema() {
+    use sea_schema::postgres::def::{NotNull, StringAttr};
+
+    let column = ColumnInfo {
+        name: String::from("username"),
+        col_type: ColumnType::Varchar(StringAttr { length: Some(50) }),
+        default: None,
+        generated: None,
+        not_null: Some(NotNull),
+        is_identity: false,
+    };
+    assert_eq!(
+        column_schema(&Schema::default(), &column),
+        json!({"type": "string", "maxLength": 50})
+    );
+    assert!(is_required(&column));
+
+    let column = ColumnInfo {
+        not_null: None,
+        ..column
+    };
+    assert_eq!(
+        column_schema(&Schema::default(), &column),
+        json!({"type": ["string", "null"], "maxLength": 50})
+    );
+    assert!(!is_required(&column));
+}
+
+#[test]
+fn openapi_numbers_as_text() {
+    let column = ColumnInfo {
+        name: String::from("price"),
+        col_type: ColumnType::Numeric(Default::default()),
+        default: None,
+        generated: None,
+        not_null: None,
+        is_identity: false,
+    };
+    assert_eq!(
+        column_schema(&Schema::default(), &column),
+        json!({"type": ["number", "string", "null"]})
+    );
+
+    let column = ColumnInfo {
+        name: String::from("rating"),
+        col_type: ColumnType::Integer,
+        ..column
+    };
+    assert_eq!(
+        column_schema(&Schema::default(), &column)["type"],
+        json!(["integer", "string", "null"])
+    );
+}
@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Context;
+use hyper::StatusCode;
+use serde::Deserialize;
+
+use crate::{MyError, Result};
+
+/// Declarative table and column permissions, read from the `policy_file`.
+///
+/// ```toml
+/// # column rules for every role
+/// [tables.accounts]
+/// hidden_columns = ["password"]
+/// read_only_columns = ["created_on"]
+///
+/// [roles.web_user.tables.accounts]
+/// actions = ["read", "insert", "update"]
+/// ```
+///
+/// Once roles are listed a role can only use the tables listed for it. Without a file, or for
+/// requests without a role, only the column rules for every role apply.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policies {
+    pub tables: HashMap<String, ColumnRules>,
+    pub roles: HashMap<String, RolePolicy>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnRules {
+    /// Never selected nor written
+    pub hidden_columns: Vec<String>,
+    /// Selected but never written
+    pub read_only_columns: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RolePolicy {
+    pub tables: HashMap<String, TablePolicy>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TablePolicy {
+    pub actions: HashSet<Action>,
+    pub hidden_columns: Vec<String>,
+    pub read_only_columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Read,
+    Insert,
+    Update,
+    Delete,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Read => write!(f, "read"),
+            Action::Insert => write!(f, "insert into"),
+            Action::Update => write!(f, "update"),
+            Action::Delete => write!(f, "delete from"),
+        }
+    }
+}
+
+/// The columns a request may not select or write on one table.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TableAccess {
+    pub table_name: String,
+    pub hidden_columns: HashSet<String>,
+    pub read_only_columns: HashSet<String>,
+}
+
+impl TableAccess {
+    pub fn is_hidden(&self, column_name: &str) -> bool {
+        self.hidden_columns.contains(column_name)
+    }
+
+    pub fn check_readable(&self, column_name: &str) -> Result<()> {
+        if self.is_hidden(column_name) {
+            return Err(forbidden(format!(
+                "column {column_name} of {} is not readable",
+                self.table_name
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn check_writable(&self, column_name: &str) -> Result<()> {
+        if self.is_hidden(column_name) || self.read_only_columns.contains(column_name) {
+            return Err(forbidden(format!(
+                "column {column_name} of {} is read-only",
+                self.table_name
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Policies {
+    pub fn from_file(path: &Path) -> anyhow::Result<Policies> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read policy file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid policy file {}", path.display()))
+    }
+
+    /// Checks the role may perform the action on the table, answers 403 otherwise.
+    pub fn table_access(
+        &self,
+        role: Option<&str>,
+        table_name: &str,
+        action: Action,
+    ) -> Result<TableAccess> {
+        let mut access = TableAccess {
+            table_name: table_name.to_string(),
+            ..TableAccess::default()
+        };
+        if let Some(rules) = self.tables.get(table_name) {
+            access
+                .hidden_columns
+                .extend(rules.hidden_columns.iter().cloned());
+            access
+                .read_only_columns
+                .extend(rules.read_only_columns.iter().cloned());
+        }
+
+        let Some(role) = role.filter(|_| !self.roles.is_empty()) else {
+            return Ok(access);
+        };
+        let table_policy = self
+            .roles
+            .get(role)
+            .and_then(|role_policy| role_policy.tables.get(table_name))
+            .filter(|table_policy| table_policy.actions.contains(&action))
+            .ok_or_else(|| forbidden(format!("role {role} may not {action} {table_name}")))?;
+        access
+            .hidden_columns
+            .extend(table_policy.hidden_columns.iter().cloned());
+        access
+            .read_only_columns
+            .extend(table_policy.read_only_columns.iter().cloned());
+
+        Ok(access)
+    }
+}
+
+fn forbidden(message: String) -> MyError {
+    MyError::from(anyhow::anyhow!(message)).with_status(StatusCode::FORBIDDEN)
+}
+
+#[test]
+fn policies_table_access() {
+    let policies: Policies = toml::from_str(
+        r#"
+        [tables.accounts]
+        hidden_columns = ["password"]
+        read_only_columns = ["created_on"]
+
+        [roles.web_user.tables.accounts]
+        actions = ["read", "insert"]
+        hidden_columns = ["email"]
+        "#,
+    )
+    .unwrap();
+
+    let access = policies
+        .table_access(Some("web_user"), "accounts", Action::Read)
+        .unwrap();
+    assert!(access.is_hidden("password"));
+    assert!(access.is_hidden("email"));
+    assert!(access.check_readable("username").is_ok());
+    assert_eq!(
+        access.check_writable("created_on").unwrap_err().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let error = policies
+        .table_access(Some("web_user"), "accounts", Action::Update)
+        .unwrap_err();
+    assert_eq!(error.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        error.body().message,
+        "role web_user may not update accounts"
+    );
+    assert!(policies
+        .table_access(Some("web_anon"), "books", Action::Read)
+        .is_err());
+
+    // without a role only the rules for every role apply
+    let access = policies
+        .table_access(None, "accounts", Action::Update)
+        .unwrap();
+    assert!(access.is_hidden("password"));
+    assert!(!access.is_hidden("email"));
+}
@@ -0,0 +1,52 @@
+use std::time::Instant;
+
+use metrics::{decrement_gauge, gauge, histogram, increment_gauge};
+use sqlx_core::pool::PoolConnection;
+use sqlx_core::postgres::{PgPool, Postgres};
+
+use crate::Result;
+
+pub const IN_USE_GAUGE: &str = "restql_pool_connections_in_use";
+pub const IDLE_GAUGE: &str = "restql_pool_connections_idle";
+pub const WAITERS_GAUGE: &str = "restql_pool_waiters";
+pub const ACQUIRE_HISTOGRAM: &str = "restql_pool_acquire_duration_seconds";
+
+/// Acquires a connection, keeping track of the callers waiting on the pool and how long
+/// they waited. Fails with a 503 when the configured acquire timeout elapses.
+pub async fn acquire(pool: &PgPool) -> Result<PoolConnection<Postgres>> {
+    let start = Instant::now();
+    let waiter = Waiter::new();
+    let connection = pool.acquire().await;
+    drop(waiter);
+    histogram!(ACQUIRE_HISTOGRAM, start.elapsed().as_secs_f64());
+
+    record_gauges(pool);
+
+    Ok(connection?)
+}
+
+/// Counted in the waiters gauge while alive, so callers that stop waiting because
+/// their request was dropped are uncounted too.
+struct Waiter;
+
+impl Waiter {
+    fn new() -> Waiter {
+        increment_gauge!(WAITERS_GAUGE, 1.0);
+        Waiter
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        decrement_gauge!(WAITERS_GAUGE, 1.0);
+    }
+}
+
+/// Updates the in-use and idle connection gauges.
+pub fn record_gauges(pool: &PgPool) {
+    let idle = pool.num_idle();
+    let in_use = (pool.size() as usize).saturating_sub(idle);
+
+    gauge!(IN_USE_GAUGE, in_use as f64);
+    gauge!(IDLE_GAUGE, idle as f64);
+}
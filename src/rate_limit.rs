@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, MatchedPath, State};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::auth::Identity;
+use crate::config::RateLimitConfig;
+use crate::{AppState, MyError, Result};
+
+/// Buckets that are full again are dropped once there are more than this, so clients
+/// that went away do not keep their bucket forever.
+const MAX_BUCKETS: usize = 10_000;
+
+/// At most `requests` per `per_secs`, bursts of up to `requests` are allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per_secs: u64,
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    /// `requests/per_secs`, like `600/60`.
+    fn from_str(input: &str) -> anyhow::Result<RateLimit> {
+        let (requests, per_secs) = input
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("expected requests/per_secs, like 600/60"))?;
+        Ok(RateLimit {
+            requests: requests.trim().parse()?,
+            per_secs: per_secs.trim().parse()?,
+        })
+    }
+}
+
+/// Token buckets, keyed by whoever is limited.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// From here on the bucket is the same as a new one
+    full_at: Instant,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of the key, answers 429 with the time until the next
+    /// token as `Retry-After` when it is empty.
+    pub fn check(&self, key: &str, limit: RateLimit) -> Result<()> {
+        self.check_at(key, limit, Instant::now())
+    }
+
+    /// Like `check`, without taking a token.
+    pub fn peek(&self, key: &str, limit: RateLimit) -> Result<()> {
+        self.take_at(key, limit, Instant::now(), false)
+    }
+
+    fn check_at(&self, key: &str, limit: RateLimit, now: Instant) -> Result<()> {
+        self.take_at(key, limit, now, true)
+    }
+
+    fn take_at(&self, key: &str, limit: RateLimit, now: Instant, take: bool) -> Result<()> {
+        if limit.requests == 0 {
+            return Err(too_many_requests(Duration::from_secs(limit.per_secs)));
+        }
+        let capacity = f64::from(limit.requests);
+        let secs_per_token = limit.per_secs as f64 / capacity;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+        if !take && !buckets.contains_key(key) {
+            return Ok(());
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            full_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        if secs_per_token > 0.0 {
+            bucket.tokens = (bucket.tokens + elapsed / secs_per_token).min(capacity);
+        } else {
+            bucket.tokens = capacity;
+        }
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            if !take {
+                return Ok(());
+            }
+            bucket.tokens -= 1.0;
+            bucket.full_at =
+                now + Duration::from_secs_f64((capacity - bucket.tokens) * secs_per_token);
+            return Ok(());
+        }
+        let wait = (1.0 - bucket.tokens) * secs_per_token;
+        Err(too_many_requests(Duration::from_secs_f64(wait)))
+    }
+}
+
+/// The configured limits with their buckets, and the permits for Lua scripts.
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    config: RateLimitConfig,
+    limiter: RateLimiter,
+    scripts: Option<Arc<Semaphore>>,
+}
+
+impl RateLimits {
+    pub fn new(config: RateLimitConfig) -> RateLimits {
+        RateLimits {
+            scripts: config
+                .max_concurrent_scripts
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+            config,
+            limiter: RateLimiter::default(),
+        }
+    }
+
+    /// Scopes and limits that apply to a request on the route, first path segment is the
+    /// table for table routes.
+    fn limits(&self, route: &str, path: &str) -> Vec<(String, RateLimit)> {
+        let mut limits = Vec::new();
+        if let Some(limit) = self.config.default {
+            limits.push((String::from("default"), limit));
+        }
+        if let Some(limit) = self.config.routes.get(route) {
+            limits.push((format!("route:{route}"), *limit));
+        }
+        if route.starts_with("/:table_name") {
+            let table = path.trim_start_matches('/').split('/').next();
+            if let Some((table, limit)) =
+                table.and_then(|table| Some((table, self.config.tables.get(table)?)))
+            {
+                limits.push((format!("table:{table}"), *limit));
+            }
+        }
+        limits
+    }
+
+    /// Takes a token from every bucket of the client that applies to the request.
+    pub fn check(&self, client: &str, route: &str, path: &str) -> Result<()> {
+        for (scope, limit) in self.limits(route, path) {
+            self.limiter.check(&format!("{client} {scope}"), limit)?;
+        }
+        Ok(())
+    }
+
+    /// Answers 429 when a bucket of the client that applies to the request is empty,
+    /// without taking tokens.
+    pub fn peek(&self, client: &str, route: &str, path: &str) -> Result<()> {
+        for (scope, limit) in self.limits(route, path) {
+            self.limiter.peek(&format!("{client} {scope}"), limit)?;
+        }
+        Ok(())
+    }
+
+    /// Held while a Lua script runs, answers 429 when `max_concurrent_scripts` are running.
+    pub fn script_permit(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        let Some(scripts) = &self.scripts else {
+            return Ok(None);
+        };
+        match scripts.clone().try_acquire_owned() {
+            Ok(permit) => Ok(Some(permit)),
+            Err(_) => Err(MyError::from(anyhow::anyhow!(
+                "too many scripts running, retry in 1 seconds"
+            ))
+            .with_status(StatusCode::TOO_MANY_REQUESTS)
+            .with_retry_after(1)),
+        }
+    }
+}
+
+/// Middleware applying the rate limits, clients are told when to retry with `Retry-After`.
+/// On the table routes it runs inside `authenticate`, so verified clients are limited by
+/// their token subject or api key instead of their IP address.
+pub async fn limit_requests<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    let client = request
+        .extensions()
+        .get::<Identity>()
+        .and_then(Identity::rate_limit_key)
+        .unwrap_or_else(|| client_ip(&state, &request));
+    state
+        .rate_limits
+        .check(&client, &route(&request), request.uri().path())?;
+
+    Ok(next.run(request).await)
+}
+
+/// Takes a token from the buckets of the IP address, for requests that failed to
+/// authenticate.
+pub fn check_client_ip<B>(state: &AppState, request: &Request<B>) -> Result<()> {
+    state.rate_limits.check(
+        &client_ip(state, request),
+        &route(request),
+        request.uri().path(),
+    )
+}
+
+/// Answers 429 when the IP address is out of tokens, without taking one.
+pub fn peek_client_ip<B>(state: &AppState, request: &Request<B>) -> Result<()> {
+    state.rate_limits.peek(
+        &client_ip(state, request),
+        &route(request),
+        request.uri().path(),
+    )
+}
+
+fn route<B>(request: &Request<B>) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default()
+}
+
+/// The IP address of the client, from `X-Forwarded-For` only when it is trusted.
+fn client_ip<B>(state: &AppState, request: &Request<B>) -> String {
+    let forwarded_for = request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(|address| address.trim().to_string())
+        .filter(|_| state.rate_limits.config.trust_forwarded_for);
+    let address = forwarded_for.or_else(|| {
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip().to_string())
+    });
+
+    format!("ip:{}", address.unwrap_or_default())
+}
+
+fn too_many_requests(retry_after: Duration) -> MyError {
+    // a partial second is rounded up, retrying earlier would be refused again
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    MyError::from(anyhow::anyhow!(
+        "rate limit exceeded, retry in {seconds} seconds"
+    ))
+    .with_status(StatusCode::TOO_MANY_REQUESTS)
+    .with_retry_after(seconds)
+}
+
+#[test]
+fn rate_limiter_refills_buckets() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit {
+        requests: 2,
+        per_secs: 10,
+    };
+    let start = Instant::now();
+
+    assert!(limiter.check_at("job", limit, start).is_ok());
+    assert!(limiter.check_at("job", limit, start).is_ok());
+    let error = limiter.check_at("job", limit, start).unwrap_err();
+    assert_eq!(error.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error.retry_after, Some(5));
+
+    // other keys have their own bucket
+    assert!(limiter.check_at("other", limit, start).is_ok());
+
+    // peeking neither takes a token nor creates a bucket
+    assert!(limiter.take_at("new", limit, start, false).is_ok());
+    assert!(limiter.take_at("job", limit, start, false).is_err());
+
+    let later = start + Duration::from_secs(5);
+    assert!(limiter.check_at("job", limit, later).is_ok());
+    assert!(limiter.check_at("job", limit, later).is_err());
+}
+
+#[test]
+fn rate_limit_from_str() {
+    assert_eq!(
+        "600/60".parse::<RateLimit>().unwrap(),
+        RateLimit {
+            requests: 600,
+            per_secs: 60,
+        }
+    );
+    assert!("600".parse::<RateLimit>().is_err());
+    assert!("600/minute".parse::<RateLimit>().is_err());
+}
+
+#[test]
+fn rate_limits_per_route_and_table() {
+    let config: RateLimitConfig = toml::from_str(
+        r#"
+        default = { requests = 100, per_secs = 60 }
+
+        [routes]
+        "/_schema/:table_name" = { requests = 1, per_secs = 60 }
+
+        [tables]
+        accounts = { requests = 1, per_secs = 60 }
+        "#,
+    )
+    .unwrap();
+    let limits = RateLimits::new(config);
+
+    let scopes = |route: &str, path: &str| -> Vec<String> {
+        limits
+            .limits(route, path)
+            .into_iter()
+            .map(|(scope, _)| scope)
+            .collect()
+    };
+    assert_eq!(
+        scopes("/:table_name/:record_id", "/accounts/1"),
+        vec!["default", "table:accounts"]
+    );
+    assert_eq!(scopes("/:table_name", "/books"), vec!["default"]);
+    assert_eq!(
+        scopes("/_schema/:table_name", "/_schema/accounts"),
+        vec!["default", "route:/_schema/:table_name"]
+    );
+
+    assert!(limits.check("ip:1", "/:table_name", "/accounts").is_ok());
+    assert!(limits.check("ip:1", "/:table_name", "/accounts").is_err());
+    // buckets are per client
+    assert!(limits.check("ip:2", "/:table_name", "/accounts").is_ok());
+    assert!(limits.check("ip:1", "/:table_name", "/books").is_ok());
+}
+
+#[test]
+fn rate_limits_concurrent_scripts() {
+    let limits = RateLimits::new(RateLimitConfig {
+        max_concurrent_scripts: Some(1),
+        ..RateLimitConfig::default()
+    });
+
+    let permit = limits.script_permit().unwrap();
+    assert!(permit.is_some());
+    let error = limits.script_permit().unwrap_err();
+    assert_eq!(error.status(), StatusCode::TOO_MANY_REQUESTS);
+    drop(permit);
+    assert!(limits.script_permit().is_ok());
+
+    assert!(RateLimits::default().script_permit().unwrap().is_none());
+}
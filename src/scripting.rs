@@ -1,3 +1,4 @@
+use crate::auth::Identity;
 use crate::methods;
 use crate::AppState;
 use crate::JsonMap;
@@ -12,6 +13,7 @@ use sqlx_core::connection::Connection;
 use std::sync::Arc;
 use tokio::sync::oneshot::error::RecvError;
 use tokio::sync::{mpsc, oneshot};
+use tracing::Instrument;
 
 // only usefull for not luau
 // fn default_opts() -> StdLib {
@@ -206,85 +208,102 @@ pub enum CommitOrRollback {
     RollbackError(MyError),
 }
 
+#[tracing::instrument(name = "lua transaction", skip_all)]
 pub async fn transaction(
     app_state: AppState,
     script: &str,
     input: &serde_json::Value,
 ) -> Result<serde_json::Value> {
+    let _permit = app_state.rate_limits.script_permit()?;
     let (cmd_tx, mut cmd_rx) =
         mpsc::channel::<(Command, oneshot::Sender<mlua::Result<serde_json::Value>>)>(100);
 
     let app_state_transaction = app_state.clone();
     let cmd_sender = cmd_tx.clone();
-    let transaction_join = tokio::spawn(async move {
-        let mut client = app_state_transaction.clone().pool.acquire().await?;
-        let mut transaction = client.begin().await?;
-
-        let app_state_copy = app_state_transaction.clone();
-        let mut result_container = None;
-        while let Some((command, responder)) = cmd_rx.recv().await {
-            match command {
-                Command::Get(table, record_id) => {
-                    let response = methods::get_record(
-                        &mut transaction,
-                        (table, record_id),
-                        app_state_copy.clone(),
-                    )
-                    .await
-                    .map(|data| {
-                        serde_json::to_value(data).expect("value cannot be converted to json")
-                    })
-                    .map_err(|e| e.into());
+    let transaction_join = tokio::spawn(
+        async move {
+            let _in_flight = app_state_transaction.transactions.enter();
+            let mut client = app_state_transaction.acquire().await?;
+            let mut transaction = client.begin().await?;
+
+            let app_state_copy = app_state_transaction.clone();
+            let mut result_container = None;
+            while let Some((command, responder)) = cmd_rx.recv().await {
+                match command {
+                    Command::Get(table, record_id) => {
+                        let span = tracing::info_span!("transaction:get", table = %table);
+                        let response = methods::get_record(
+                            &mut transaction,
+                            (table, record_id),
+                            &Identity::default(),
+                            app_state_copy.clone(),
+                        )
+                        .instrument(span)
+                        .await
+                        .map(|data| {
+                            serde_json::to_value(data).expect("value cannot be converted to json")
+                        })
+                        .map_err(|e| e.into());
 
-                    responder.send(response).unwrap();
-                }
-                Command::Create(table, data) => {
-                    let response = methods::insert_record(&mut transaction, table, data)
+                        responder.send(response).unwrap();
+                    }
+                    Command::Create(table, data) => {
+                        let span = tracing::info_span!("transaction:create", table = %table);
+                        let response = methods::insert_record(
+                            &mut transaction,
+                            table,
+                            data,
+                            &Identity::default(),
+                            app_state_copy.clone(),
+                        )
+                        .instrument(span)
                         .await
                         .map(|data| {
                             serde_json::to_value(data).expect("value cannot be converted to json")
                         })
                         .map_err(|e| e.into());
 
-                    responder.send(response).unwrap();
-                }
-                Command::Rollback(value) => {
-                    result_container = Some(CommitOrRollback::Rollback(value));
-                    break;
-                }
-                Command::Done(value) => {
-                    result_container = Some(CommitOrRollback::Commit(value));
-                    break;
-                }
-                Command::Error(value) => {
-                    result_container = Some(CommitOrRollback::RollbackError(value));
-                    break;
+                        responder.send(response).unwrap();
+                    }
+                    Command::Rollback(value) => {
+                        result_container = Some(CommitOrRollback::Rollback(value));
+                        break;
+                    }
+                    Command::Done(value) => {
+                        result_container = Some(CommitOrRollback::Commit(value));
+                        break;
+                    }
+                    Command::Error(value) => {
+                        result_container = Some(CommitOrRollback::RollbackError(value));
+                        break;
+                    }
                 }
             }
-        }
 
-        match result_container {
-            Some(CommitOrRollback::Commit(value)) => {
-                if let Err(e) = transaction.commit().await {
-                    eprintln!("{:?}", e);
+            match result_container {
+                Some(CommitOrRollback::Commit(value)) => {
+                    if let Err(e) = transaction.commit().await {
+                        tracing::error!(error = ?e, "committing transaction failed");
+                    }
+                    Ok(value)
                 }
-                Ok(value)
-            }
-            Some(CommitOrRollback::RollbackError(value)) => {
-                if let Err(e) = transaction.rollback().await {
-                    eprintln!("{:?}", e);
+                Some(CommitOrRollback::RollbackError(value)) => {
+                    if let Err(e) = transaction.rollback().await {
+                        tracing::error!(error = ?e, "rolling back transaction failed");
+                    }
+                    Err(value)
                 }
-                Err(value)
-            }
-            Some(CommitOrRollback::Rollback(value)) => {
-                if let Err(e) = transaction.rollback().await {
-                    eprintln!("{:?}", e);
+                Some(CommitOrRollback::Rollback(value)) => {
+                    if let Err(e) = transaction.rollback().await {
+                        tracing::error!(error = ?e, "rolling back transaction failed");
+                    }
+                    Ok(value)
                 }
-                Ok(value)
+                _ => panic!("invalid "),
             }
-            _ => panic!("invalid "),
         }
-    });
+        .in_current_span(),
+    );
 
     let (left, right) = tokio::join!(
         something(app_state.clone(), script, input, cmd_sender.clone())
@@ -322,10 +341,28 @@ async fn test_app_state() -> AppState {
         .password("example");
 
     let pool_opts = PoolOptions::new();
-    let pool = pool_opts.connect_with(connect_opts).await.unwrap();
-
-    let pool = pool;
-    let app_state = AppState { pool };
+    let pool = pool_opts.connect_with(connect_opts.clone()).await.unwrap();
+
+    let schema = crate::schema::Schema::discover(&pool, "public").await;
+    let validators = crate::json_schema::Validators::new(&schema).unwrap();
+    let app_state = AppState {
+        pool,
+        schema: std::sync::Arc::new(schema),
+        validators: std::sync::Arc::new(validators),
+        transforms: Default::default(),
+        binary_content_types: Default::default(),
+        redact_columns: Default::default(),
+        auth: None,
+        api_keys: Default::default(),
+        policies: Default::default(),
+        request_headers: Default::default(),
+        rate_limits: Default::default(),
+        limits: Default::default(),
+        statement_timeouts: Default::default(),
+        connect_options: std::sync::Arc::new(connect_opts),
+        transactions: Default::default(),
+        metrics: None,
+    };
     app_state
 }
 
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHasher, SaltString};
+use argon2::Argon2;
+use sea_schema::postgres::def::{ColumnType, TableDef};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::json_schema::Operation;
+use crate::schema::{self, Schema};
+use crate::{JsonMap, MyError, Result, Value};
+
+/// Length of an argon2id PHC string with the default parameters.
+const ARGON2_LENGTH: u16 = 97;
+const BCRYPT_LENGTH: u16 = 60;
+
+/// Applied to a column value before it is bound, configured per `table.column`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transform {
+    /// Stores an argon2id PHC string, the column is left out of `RETURNING`
+    Argon2,
+    /// Stores a bcrypt hash, the column is left out of `RETURNING`
+    Bcrypt,
+    Lowercase,
+    Trim,
+    /// Generates a random uuid on insert, the sent value is ignored
+    Uuid,
+    /// Sets the current time on insert, the sent value is ignored
+    Now,
+}
+
+impl Transform {
+    fn is_hash(self) -> bool {
+        matches!(self, Transform::Argon2 | Transform::Bcrypt)
+    }
+
+    /// Whether the column can store the value, so a misconfiguration is caught at startup
+    /// instead of by every insert.
+    fn fits(self, column_type: &ColumnType) -> bool {
+        let hash_length = match self {
+            Transform::Argon2 => ARGON2_LENGTH,
+            Transform::Bcrypt => BCRYPT_LENGTH,
+            Transform::Now => {
+                return matches!(
+                    column_type,
+                    ColumnType::Timestamp(_) | ColumnType::TimestampWithTimeZone(_)
+                )
+            }
+            _ => return true,
+        };
+        // char pads the hash with spaces, which no longer verifies
+        match column_type {
+            ColumnType::Text => true,
+            ColumnType::Varchar(attr) => match attr.length {
+                Some(length) => length >= hash_length,
+                None => true,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Transforms {
+    /// Keyed by `table.column`, applied in order
+    columns: HashMap<String, Vec<Transform>>,
+}
+
+impl Transforms {
+    /// Checks every `table.column` exists and can store what its transforms produce.
+    pub fn new(
+        columns: HashMap<String, Vec<Transform>>,
+        schema: &Schema,
+    ) -> anyhow::Result<Transforms> {
+        for (key, transforms) in &columns {
+            let (table_name, column_name) = key.split_once('.').ok_or_else(|| {
+                anyhow::anyhow!("transforms are keyed by table.column, got {key}")
+            })?;
+            let table = schema.table(table_name).map_err(|e| e.source)?;
+            let column = schema::column(table, column_name).map_err(|e| e.source)?;
+            for transform in transforms {
+                if !transform.fits(&column.col_type) {
+                    anyhow::bail!(
+                        "transform {transform:?} does not fit column {key} of type {}",
+                        schema::type_name(&column.col_type)
+                    );
+                }
+            }
+        }
+
+        Ok(Transforms { columns })
+    }
+
+    fn column_transforms(&self, table_name: &str, column_name: &str) -> &[Transform] {
+        self.columns
+            .get(&format!("{table_name}.{column_name}"))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Columns whose stored value is a hash, never returned after a write.
+    pub fn hashed_columns(&self, table_name: &str) -> Vec<String> {
+        self.columns
+            .iter()
+            .filter(|(_, transforms)| transforms.iter().any(|transform| transform.is_hash()))
+            .filter_map(|(key, _)| key.strip_prefix(table_name)?.strip_prefix('.'))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Generates the uuid and timestamp columns on insert and trims or lowercases the sent
+    /// values, these are validated afterwards.
+    pub fn normalise(&self, table: &TableDef, mut data: JsonMap, operation: Operation) -> JsonMap {
+        for column in &table.columns {
+            for transform in self.column_transforms(&table.info.name, &column.name) {
+                match (transform, operation) {
+                    (Transform::Uuid, Operation::Insert) => {
+                        let uuid = Uuid::new_v4();
+                        let value = match column.col_type {
+                            ColumnType::Uuid => Value::Uuid(uuid),
+                            _ => Value::String(uuid.to_string()),
+                        };
+                        data.insert(column.name.clone(), value);
+                    }
+                    (Transform::Now, Operation::Insert) => {
+                        let now = OffsetDateTime::now_utc();
+                        let value = match column.col_type {
+                            ColumnType::Timestamp(_) => {
+                                Value::DateTime(PrimitiveDateTime::new(now.date(), now.time()))
+                            }
+                            _ => Value::DateTimeTz(now),
+                        };
+                        data.insert(column.name.clone(), value);
+                    }
+                    (Transform::Lowercase, _) => {
+                        if let Some(Value::String(text)) = data.get_mut(&column.name) {
+                            *text = text.to_lowercase();
+                        }
+                    }
+                    (Transform::Trim, _) => {
+                        if let Some(Value::String(text)) = data.get_mut(&column.name) {
+                            *text = text.trim().to_string();
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        data
+    }
+
+    /// Replaces the values of hashed columns by their hash, on a blocking thread as hashing
+    /// is slow on purpose.
+    pub async fn hash(&self, table_name: &str, mut data: JsonMap) -> Result<JsonMap> {
+        for (column_name, value) in data.iter_mut() {
+            for transform in self.column_transforms(table_name, column_name) {
+                if !transform.is_hash() {
+                    continue;
+                }
+                let Value::String(plain) = value else {
+                    return Err(MyError::from(anyhow::anyhow!(
+                        "column {column_name} expects a string to hash"
+                    )));
+                };
+
+                let plain = std::mem::take(plain);
+                let transform = *transform;
+                let hashed =
+                    tokio::task::spawn_blocking(move || hash_value(transform, &plain)).await??;
+                *value = Value::String(hashed);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+fn hash_value(transform: Transform, plain: &str) -> anyhow::Result<String> {
+    match transform {
+        Transform::Argon2 => {
+            let salt = SaltString::generate(&mut OsRng);
+            let hash = Argon2::default()
+                .hash_password(plain.as_bytes(), &salt)
+                .map_err(|e| anyhow::anyhow!("argon2 hashing failed: {e}"))?;
+            Ok(hash.to_string())
+        }
+        Transform::Bcrypt => Ok(bcrypt::hash(plain, bcrypt::DEFAULT_COST)?),
+        _ => Ok(plain.to_string()),
+    }
+}
+
+#[cfg(test)]
+fn accounts_table() -> TableDef {
+    use sea_schema::postgres::def::{ColumnInfo, StringAttr, TableInfo};
+
+    let column = |name: &str, col_type| ColumnInfo {
+        name: name.to_string(),
+        col_type,
+        default: None,
+        generated: None,
+        not_null: None,
+        is_identity: false,
+    };
+
+    TableDef {
+        info: TableInfo {
+            name: String::from("accounts"),
+            of_type: None,
+        },
+        columns: vec![
+            column("id", ColumnType::Uuid),
+            column("email", ColumnType::Varchar(StringAttr { length: None })),
+            column("password", ColumnType::Text),
+        ],
+        check_constraints: Vec::new(),
+        not_null_constraints: Vec::new(),
+        unique_constraints: Vec::new(),
+        primary_key_constraints: Vec::new(),
+        reference_constraints: Vec::new(),
+        exclusion_constraints: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn transforms_normalise_and_hash() {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+    let mut schema = Schema::default();
+    schema
+        .tables
+        .insert(String::from("accounts"), accounts_table());
+    let transforms = Transforms::new(
+        HashMap::from([
+            (String::from("accounts.id"), vec![Transform::Uuid]),
+            (
+                String::from("accounts.email"),
+                vec![Transform::Trim, Transform::Lowercase],
+            ),
+            (String::from("accounts.password"), vec![Transform::Argon2]),
+        ]),
+        &schema,
+    )
+    .unwrap();
+    let data = JsonMap::from([
+        (String::from("id"), Value::String(String::from("mine"))),
+        (
+            String::from("email"),
+            Value::String(String::from(" Hoi@Example.com ")),
+        ),
+        (String::from("password"), Value::String(String::from("hoi"))),
+    ]);
+
+    let data = transforms.normalise(&accounts_table(), data, Operation::Insert);
+    assert!(matches!(data["id"], Value::Uuid(_)));
+    assert_eq!(
+        data["email"],
+        Value::String(String::from("hoi@example.com"))
+    );
+
+    let data = transforms.hash("accounts", data).await.unwrap();
+    let Value::String(hash) = &data["password"] else {
+        panic!("password is not a string");
+    };
+    assert!(Argon2::default()
+        .verify_password(b"hoi", &PasswordHash::new(hash).unwrap())
+        .is_ok());
+    assert_eq!(transforms.hashed_columns("accounts"), vec!["password"]);
+
+    let data = JsonMap::from([(String::from("password"), Value::Int(5))]);
+    assert!(transforms.hash("accounts", data).await.is_err());
+}
+
+#[test]
+fn transforms_refuse_unfit_columns() {
+    use sea_schema::postgres::def::StringAttr;
+
+    let mut table = accounts_table();
+    table.columns[1].col_type = ColumnType::Varchar(StringAttr { length: Some(60) });
+    let mut schema = Schema::default();
+    schema.tables.insert(String::from("accounts"), table);
+    let transforms = |key: &str, transform| {
+        Transforms::new(
+            HashMap::from([(String::from(key), vec![transform])]),
+            &schema,
+        )
+    };
+
+    assert!(transforms("accounts.email", Transform::Bcrypt).is_ok());
+    assert!(transforms("accounts.email", Transform::Argon2).is_err());
+    assert!(transforms("accounts.password", Transform::Argon2).is_ok());
+    assert!(transforms("accounts.id", Transform::Argon2).is_err());
+    assert!(transforms("accounts.email", Transform::Now).is_err());
+    assert!(transforms("accounts.missing", Transform::Trim).is_err());
+    assert!(transforms("books.title", Transform::Trim).is_err());
+}
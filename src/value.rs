@@ -5,7 +5,7 @@ use sqlx_core::column::Column;
 use sqlx_core::database::{Database, HasValueRef};
 use sqlx_core::decode::Decode;
 use sqlx_core::from_row::FromRow;
-use sqlx_core::postgres::{PgRow, PgValueFormat, PgValueRef, Postgres};
+use sqlx_core::postgres::{PgRow, PgTypeInfo, PgTypeKind, PgValueFormat, PgValueRef, Postgres};
 use sqlx_core::row::Row;
 use sqlx_core::type_info::TypeInfo;
 use sqlx_core::types::Type;
@@ -16,6 +16,8 @@ use uuid::Uuid;
 
 use self::datetime_iso8601::parse_datetime;
 
+pub mod base64;
+pub mod coerce;
 pub mod datetime_iso8601;
 mod serde;
 
@@ -42,6 +44,9 @@ pub enum Value {
     #[serde(with = "datetime_iso8601")]
     DateTime(PrimitiveDateTime),
     String(String),
+    #[serde(serialize_with = "self::base64::serialize")]
+    Bytes(Vec<u8>),
+    Json(serde_json::Value),
 }
 
 impl Value {
@@ -80,6 +85,8 @@ impl ToSql for Value {
             Value::DateTimeTz(x) => x.to_sql(ty, out),
             Value::DateTime(x) => x.to_sql(ty, out),
             Value::String(x) => x.to_sql(ty, out),
+            Value::Bytes(x) => x.to_sql(ty, out),
+            Value::Json(x) => x.to_sql(ty, out),
         }
     }
 
@@ -91,6 +98,8 @@ impl ToSql for Value {
             || OffsetDateTime::accepts(ty)
             || PrimitiveDateTime::accepts(ty)
             || String::accepts(ty)
+            || Vec::<u8>::accepts(ty)
+            || serde_json::Value::accepts(ty)
     }
 
     tokio_postgres::types::to_sql_checked!();
@@ -178,6 +187,14 @@ impl<DB: Database> Type<DB> for Value {
     }
 }
 
+/// Domains are decoded as their base type.
+fn base_type(type_info: &PgTypeInfo) -> &PgTypeInfo {
+    match type_info.kind() {
+        PgTypeKind::Domain(base) => base_type(base),
+        _ => type_info,
+    }
+}
+
 impl FromRow<'_, PgRow> for OptionalJsonMapWrapper {
     fn from_row(row: &PgRow) -> sqlx_core::error::Result<OptionalJsonMapWrapper> {
         // Ok(Self {
@@ -191,35 +208,48 @@ impl FromRow<'_, PgRow> for OptionalJsonMapWrapper {
         for column in row.columns() {
             let column_name = column.name();
             let index = column.ordinal();
-            let Ok(raw_value) = row.try_get_raw(column.ordinal()) else {continue;};
-            let value = match raw_value.type_info().name() {
-                "BOOL" | "BOOLEAN" => row.get::<Option<bool>, _>(index).map(Value::Bool),
+            let Ok(raw_value) = row.try_get_raw(column.ordinal()) else {
+                continue;
+            };
+            let type_info = raw_value.type_info().into_owned();
+            // the type is already checked by the match, unchecked gets also work for domains
+            let value = match base_type(&type_info).name() {
+                "BOOL" | "BOOLEAN" => row.get_unchecked::<Option<bool>, _>(index).map(Value::Bool),
                 "INT2" => row
-                    .get::<Option<i16>, _>(index)
+                    .get_unchecked::<Option<i16>, _>(index)
                     .map(|x| Value::Int(x as i64)),
                 "INT4" => row
-                    .get::<Option<i32>, _>(index)
+                    .get_unchecked::<Option<i32>, _>(index)
                     .map(|x| Value::Int(x as i64)),
-                "INT8" => row.get::<Option<i64>, _>(index).map(Value::Int),
+                "INT8" => row.get_unchecked::<Option<i64>, _>(index).map(Value::Int),
                 "FLOAT4" => row
-                    .get::<Option<f32>, _>(index)
+                    .get_unchecked::<Option<f32>, _>(index)
                     .map(|x| Value::Float(x as f64)),
-                "FLOAT8" => row.get::<Option<f64>, _>(index).map(Value::Float),
+                "FLOAT8" => row.get_unchecked::<Option<f64>, _>(index).map(Value::Float),
                 "TIMESTAMP" => row
-                    .get::<Option<PrimitiveDateTime>, _>(index)
+                    .get_unchecked::<Option<PrimitiveDateTime>, _>(index)
                     .map(Value::DateTime),
                 "TIMESTAMPTZ" => row
-                    .get::<Option<OffsetDateTime>, _>(index)
+                    .get_unchecked::<Option<OffsetDateTime>, _>(index)
                     .map(Value::DateTimeTz),
-                "UUID" => row.get::<Option<Uuid>, _>(index).map(Value::Uuid),
+                "UUID" => row.get_unchecked::<Option<Uuid>, _>(index).map(Value::Uuid),
+                "JSON" | "JSONB" => row
+                    .get_unchecked::<Option<serde_json::Value>, _>(index)
+                    .map(Value::Json),
+                "BYTEA" => row
+                    .get_unchecked::<Option<Vec<u8>>, _>(index)
+                    .map(Value::Bytes),
                 _ if raw_value.is_null() => None,
-                _other_type => row.get::<Option<String>, _>(index).map(Value::String),
+                // enums are send as their label, other unknown types fail to decode
+                _other_type => row
+                    .try_get_unchecked::<Option<String>, _>(index)?
+                    .map(Value::String),
             };
 
             data.insert(column_name.to_string(), value);
         }
 
-        todo!()
+        Ok(OptionalJsonMapWrapper(data))
     }
 }
 
@@ -227,12 +257,14 @@ impl From<Value> for sea_query::Value {
     fn from(value: Value) -> Self {
         match value {
             Value::Bool(b) => sea_query::Value::Bool(Some(b)),
-            Value::DateTime(dt) => sea_query::Value::String(Some(Box::new(dt.to_string()))),
-            Value::DateTimeTz(dt) => sea_query::Value::String(Some(Box::new(dt.to_string()))),
+            Value::DateTime(dt) => sea_query::Value::TimeDateTime(Some(Box::new(dt))),
+            Value::DateTimeTz(dt) => sea_query::Value::TimeDateTimeWithTimeZone(Some(Box::new(dt))),
             Value::Float(f) => sea_query::Value::Double(Some(f)),
             Value::Int(i) => sea_query::Value::BigInt(Some(i)),
             Value::String(s) => sea_query::Value::String(Some(Box::new(s))),
-            Value::Uuid(u) => sea_query::Value::String(Some(Box::new(u.to_string()))),
+            Value::Uuid(u) => sea_query::Value::Uuid(Some(Box::new(u))),
+            Value::Bytes(b) => sea_query::Value::Bytes(Some(Box::new(b))),
+            Value::Json(j) => sea_query::Value::Json(Some(Box::new(j))),
         }
     }
 }
@@ -267,8 +299,7 @@ fn value_test_true() {
 
 #[test]
 fn value_test_uuid() {
-    let data = serde_json::from_str("\"89592c86-f85d-4527-bdb9-4c3f5dd63f2d\"").unwrap();
-    let out: Value = serde_json::from_value(data).unwrap();
+    let out = Value::parse_str("89592c86-f85d-4527-bdb9-4c3f5dd63f2d");
     assert_eq!(
         out,
         Value::Uuid("89592c86-f85d-4527-bdb9-4c3f5dd63f2d".parse().unwrap())
@@ -279,8 +310,7 @@ fn value_test_uuid() {
 fn value_test_datetime() {
     use time::macros::datetime;
 
-    let data = serde_json::from_str("\"2020-01-01T12:00:00\"").unwrap();
-    let out: Value = serde_json::from_value(data).unwrap();
+    let out = Value::parse_str("2020-01-01T12:00:00");
 
     let x = datetime!(2020-01-01 12:00:00);
     assert_eq!(out, Value::DateTime(x))
@@ -288,16 +318,28 @@ fn value_test_datetime() {
 
 #[test]
 fn value_test_datetime_tz() {
-    let data = serde_json::from_str("\"2020-01-01T12:00:00Z\"").unwrap();
-    let out: Value = serde_json::from_value(data).unwrap();
+    let out = Value::parse_str("2020-01-01T12:00:00Z");
 
     let x = OffsetDateTime::from_unix_timestamp(1577880000).unwrap();
     assert_eq!(out, Value::DateTimeTz(x))
 }
 
+#[test]
+fn value_test_string_is_not_guessed() {
+    let data = serde_json::from_str("\"2020-01-01T12:00:00\"").unwrap();
+    let out: Value = serde_json::from_value(data).unwrap();
+    assert_eq!(out, Value::String("2020-01-01T12:00:00".to_string()))
+}
+
 #[test]
 fn value_test_string() {
     let data = serde_json::from_str("\"testing\"").unwrap();
     let out: Value = serde_json::from_value(data).unwrap();
     assert_eq!(out, Value::String("testing".to_string()))
 }
+
+#[test]
+fn value_test_bytes_serialize_base64() {
+    let out = serde_json::to_value(Value::Bytes(vec![0, 1, 2, 254, 255])).unwrap();
+    assert_eq!(out, serde_json::json!("AAEC/v8="))
+}
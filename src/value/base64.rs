@@ -0,0 +1,15 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Serialize, Serializer};
+
+pub fn encode(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    STANDARD.decode(value)
+}
+
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    encode(bytes).serialize(serializer)
+}
@@ -0,0 +1,382 @@
+use sea_schema::postgres::def::{ColumnInfo, ColumnType};
+use sea_schema::sea_query;
+use time::format_description::well_known::Iso8601;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, Time};
+use uuid::Uuid;
+
+use super::datetime_iso8601::parse_datetime;
+use crate::schema::type_name;
+use crate::{MyError, Result, Value};
+
+const DATE_FORMAT: &[FormatItem<'_>] = format_description!("[year]-[month]-[day]");
+const TIME_FORMAT: &[FormatItem<'_>] = format_description!("[hour]:[minute]:[second]");
+
+fn mismatch(column: &ColumnInfo, value: &Value) -> MyError {
+    let got = match value {
+        Value::String(s) => format!("'{s}'"),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    };
+
+    MyError::from(anyhow::anyhow!(
+        "column {} expects {}, got {got}",
+        column.name,
+        type_name(&column.col_type)
+    ))
+}
+
+fn parse_text<T: std::str::FromStr>(column: &ColumnInfo, value: &Value) -> Result<T> {
+    let Value::String(s) = value else {
+        return Err(mismatch(column, value));
+    };
+    s.parse().map_err(|_| mismatch(column, value))
+}
+
+fn to_int<T: TryFrom<i64>>(column: &ColumnInfo, value: &Value) -> Result<T> {
+    let int = match value {
+        Value::Int(i) => *i,
+        _ => parse_text(column, value)?,
+    };
+    T::try_from(int).map_err(|_| mismatch(column, value))
+}
+
+fn to_float(column: &ColumnInfo, value: &Value) -> Result<f64> {
+    match value {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        _ => parse_text(column, value),
+    }
+}
+
+/// Converts incoming json (or url) data into the sql value matching the declared column type.
+pub fn coerce(column: &ColumnInfo, value: Value) -> Result<sea_query::Value> {
+    let out = match (&column.col_type, &value) {
+        (ColumnType::SmallInt | ColumnType::SmallSerial, _) => {
+            sea_query::Value::SmallInt(Some(to_int(column, &value)?))
+        }
+        (ColumnType::Integer | ColumnType::Serial, _) => {
+            sea_query::Value::Int(Some(to_int(column, &value)?))
+        }
+        (ColumnType::BigInt | ColumnType::BigSerial, _) => {
+            sea_query::Value::BigInt(Some(to_int(column, &value)?))
+        }
+        (ColumnType::Real, _) => sea_query::Value::Float(Some(to_float(column, &value)? as f32)),
+        (ColumnType::DoublePrecision, _) => {
+            sea_query::Value::Double(Some(to_float(column, &value)?))
+        }
+        (ColumnType::Decimal(_) | ColumnType::Numeric(_) | ColumnType::Money, _) => {
+            // bound as text and casted by the placeholder, so no precision is lost
+            let number = match &value {
+                Value::Int(i) => i.to_string(),
+                Value::Float(f) => f.to_string(),
+                Value::String(s) if s.parse::<f64>().is_ok() => s.clone(),
+                _ => return Err(mismatch(column, &value)),
+            };
+            sea_query::Value::String(Some(Box::new(number)))
+        }
+        (ColumnType::Boolean, Value::Bool(b)) => sea_query::Value::Bool(Some(*b)),
+        (ColumnType::Boolean, _) => sea_query::Value::Bool(Some(parse_text(column, &value)?)),
+        (ColumnType::Uuid, Value::Uuid(u)) => sea_query::Value::Uuid(Some(Box::new(*u))),
+        (ColumnType::Uuid, Value::String(s)) => {
+            let uuid = Uuid::try_parse(s).map_err(|_| mismatch(column, &value))?;
+            sea_query::Value::Uuid(Some(Box::new(uuid)))
+        }
+        (ColumnType::Timestamp(_), Value::DateTime(dt)) => {
+            sea_query::Value::TimeDateTime(Some(Box::new(*dt)))
+        }
+        (ColumnType::Timestamp(_), Value::String(s)) => {
+            let dt = parse_datetime(s).map_err(|_| mismatch(column, &value))?;
+            sea_query::Value::TimeDateTime(Some(Box::new(dt)))
+        }
+        (ColumnType::TimestampWithTimeZone(_), Value::DateTimeTz(dt)) => {
+            sea_query::Value::TimeDateTimeWithTimeZone(Some(Box::new(*dt)))
+        }
+        (ColumnType::TimestampWithTimeZone(_), Value::String(s)) => {
+            let dt = OffsetDateTime::parse(s, &Iso8601::DEFAULT)
+                .map_err(|_| mismatch(column, &value))?;
+            sea_query::Value::TimeDateTimeWithTimeZone(Some(Box::new(dt)))
+        }
+        (ColumnType::Date, Value::String(s)) => {
+            let date = Date::parse(s, &DATE_FORMAT).map_err(|_| mismatch(column, &value))?;
+            sea_query::Value::TimeDate(Some(Box::new(date)))
+        }
+        (ColumnType::Time(_), Value::String(s)) => {
+            let time = Time::parse(s, &TIME_FORMAT).map_err(|_| mismatch(column, &value))?;
+            sea_query::Value::TimeTime(Some(Box::new(time)))
+        }
+        (ColumnType::Bytea, Value::Bytes(b)) => sea_query::Value::Bytes(Some(Box::new(b.clone()))),
+        (ColumnType::Bytea, Value::String(s)) => {
+            let bytes = super::base64::decode(s).map_err(|_| mismatch(column, &value))?;
+            sea_query::Value::Bytes(Some(Box::new(bytes)))
+        }
+        (ColumnType::Enum(enum_def), Value::String(s)) => {
+            if !enum_def.values.is_empty() && !enum_def.values.contains(s) {
+                let labels = enum_def
+                    .values
+                    .iter()
+                    .map(|label| format!("'{label}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return Err(MyError::from(anyhow::anyhow!(
+                    "column {} expects one of {labels}, got '{s}'",
+                    column.name
+                )));
+            }
+            sea_query::Value::String(Some(Box::new(s.clone())))
+        }
+        (
+            ColumnType::Int4Range
+            | ColumnType::Int8Range
+            | ColumnType::NumRange
+            | ColumnType::TsRange
+            | ColumnType::TsTzRange
+            | ColumnType::DateRange,
+            Value::Json(json),
+        ) => {
+            let range = range_literal(json).ok_or_else(|| mismatch(column, &value))?;
+            sea_query::Value::String(Some(Box::new(range)))
+        }
+        // composite types and GeoJSON, converted by the placeholder
+        (ColumnType::Unknown(_), Value::Json(json)) if json.is_object() => {
+            sea_query::Value::Json(Some(Box::new(json.clone())))
+        }
+        (ColumnType::Json | ColumnType::JsonBinary, _) => {
+            sea_query::Value::Json(Some(Box::new(serde_json::to_value(&value)?)))
+        }
+        (ColumnType::Varchar(_) | ColumnType::Char(_) | ColumnType::Text, Value::String(s)) => {
+            sea_query::Value::String(Some(Box::new(s.clone())))
+        }
+        (ColumnType::Varchar(_) | ColumnType::Char(_) | ColumnType::Text, _) => {
+            return Err(mismatch(column, &value))
+        }
+        // everything else is send as text and casted to the column type by postgres
+        (_, Value::String(s)) => sea_query::Value::String(Some(Box::new(s.clone()))),
+        (_, _) => return Err(mismatch(column, &value)),
+    };
+
+    Ok(out)
+}
+
+/// Converts `{"lower": .., "upper": .., "bounds": "[)"}` to the range literal `["..","..")`,
+/// missing or null bounds are unbounded.
+fn range_literal(json: &serde_json::Value) -> Option<String> {
+    let object = json.as_object()?;
+    let bounds = match object.get("bounds") {
+        None => "[)",
+        Some(serde_json::Value::String(bounds)) => bounds.as_str(),
+        Some(_) => return None,
+    };
+    if !["[)", "[]", "(]", "()"].contains(&bounds) {
+        return None;
+    }
+
+    let element = |key: &str| -> Option<String> {
+        match object.get(key) {
+            None | Some(serde_json::Value::Null) => Some(String::new()),
+            Some(serde_json::Value::String(s)) => Some(format!(
+                "\"{}\"",
+                s.replace('\\', "\\\\").replace('"', "\\\"")
+            )),
+            Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+            Some(_) => None,
+        }
+    };
+
+    Some(format!(
+        "{}{},{}{}",
+        &bounds[..1],
+        element("lower")?,
+        element("upper")?,
+        &bounds[1..]
+    ))
+}
+
+/// Coerces a value that arrived as plain text, like a record id or a filter value.
+pub fn coerce_str(column: &ColumnInfo, value: &str) -> Result<sea_query::Value> {
+    coerce(column, Value::String(value.to_owned()))
+}
+
+/// Bind parameter for the column. Values bound as text are first typed as text, so the
+/// prepared statement does not infer the column type for the parameter, and then casted.
+pub fn placeholder(column: &ColumnInfo, value: &sea_query::Value, index: usize) -> String {
+    match (&column.col_type, value) {
+        (ColumnType::Unknown(name), sea_query::Value::Json(_)) if name == "geometry" => {
+            format!("ST_GeomFromGeoJSON((${index}::jsonb)::text)")
+        }
+        (ColumnType::Unknown(name), sea_query::Value::Json(_)) if name == "geography" => {
+            format!("ST_GeomFromGeoJSON((${index}::jsonb)::text)::geography")
+        }
+        (ColumnType::Unknown(name), sea_query::Value::Json(_)) => {
+            format!("jsonb_populate_record(NULL::{name}, ${index})")
+        }
+        (column_type, _) => cast_placeholder(column_type, index),
+    }
+}
+
+fn cast_placeholder(column_type: &ColumnType, index: usize) -> String {
+    match column_type {
+        ColumnType::SmallInt
+        | ColumnType::SmallSerial
+        | ColumnType::Integer
+        | ColumnType::Serial
+        | ColumnType::BigInt
+        | ColumnType::BigSerial
+        | ColumnType::Real
+        | ColumnType::DoublePrecision
+        | ColumnType::Boolean
+        | ColumnType::Bytea
+        | ColumnType::Uuid
+        | ColumnType::Timestamp(_)
+        | ColumnType::TimestampWithTimeZone(_)
+        | ColumnType::Date
+        | ColumnType::Time(_)
+        | ColumnType::Json
+        | ColumnType::JsonBinary
+        | ColumnType::Varchar(_)
+        | ColumnType::Char(_)
+        | ColumnType::Text => format!("${index}"),
+        other => format!("${index}::text::{}", type_name(other)),
+    }
+}
+
+#[cfg(test)]
+fn test_column(col_type: ColumnType) -> ColumnInfo {
+    ColumnInfo {
+        name: String::from("created_on"),
+        col_type,
+        default: None,
+        generated: None,
+        not_null: None,
+        is_identity: false,
+    }
+}
+
+#[test]
+fn coerce_timestamp() {
+    use sea_schema::postgres::def::TimeAttr;
+    use time::macros::datetime;
+
+    let column = test_column(ColumnType::Timestamp(TimeAttr::default()));
+    let out = coerce(&column, Value::String("2020-01-01T12:00:00".to_string())).unwrap();
+
+    assert_eq!(
+        out,
+        sea_query::Value::TimeDateTime(Some(Box::new(datetime!(2020-01-01 12:00:00))))
+    )
+}
+
+#[test]
+fn coerce_timestamp_error() {
+    use sea_schema::postgres::def::TimeAttr;
+
+    let column = test_column(ColumnType::Timestamp(TimeAttr::default()));
+    let err = coerce(&column, Value::String("abc".to_string())).unwrap_err();
+
+    assert_eq!(
+        "column created_on expects timestamp, got 'abc'",
+        err.source.to_string()
+    )
+}
+
+#[test]
+fn coerce_varchar_keeps_datetime_like_string() {
+    use sea_schema::postgres::def::StringAttr;
+
+    let column = test_column(ColumnType::Varchar(StringAttr::default()));
+    let out = coerce(&column, Value::String("2020-01-01T00:00:00".to_string())).unwrap();
+
+    assert_eq!(
+        out,
+        sea_query::Value::String(Some(Box::new("2020-01-01T00:00:00".to_string())))
+    )
+}
+
+#[test]
+fn coerce_smallint_out_of_range() {
+    let column = test_column(ColumnType::SmallInt);
+
+    assert!(coerce(&column, Value::Int(100_000)).is_err());
+    assert_eq!(
+        coerce(&column, Value::String("12".to_string())).unwrap(),
+        sea_query::Value::SmallInt(Some(12))
+    );
+}
+
+#[test]
+fn coerce_bytea_from_base64() {
+    let column = test_column(ColumnType::Bytea);
+
+    assert_eq!(
+        coerce(&column, Value::String("AAEC/v8=".to_string())).unwrap(),
+        sea_query::Value::Bytes(Some(Box::new(vec![0, 1, 2, 254, 255])))
+    );
+    assert!(coerce(&column, Value::String("not base64!".to_string())).is_err());
+}
+
+#[test]
+fn coerce_enum_lists_labels() {
+    use sea_schema::postgres::def::EnumDef;
+
+    let mut column = test_column(ColumnType::Enum(EnumDef {
+        values: vec![String::from("pending"), String::from("paid")],
+        typename: String::from("order_status"),
+    }));
+    column.name = String::from("status");
+
+    let err = coerce(&column, Value::String("shipped".to_string())).unwrap_err();
+    assert_eq!(
+        "column status expects one of 'pending', 'paid', got 'shipped'",
+        err.source.to_string()
+    );
+
+    let value = coerce(&column, Value::String("paid".to_string())).unwrap();
+    assert_eq!("$1::text::order_status", placeholder(&column, &value, 1));
+}
+
+#[test]
+fn coerce_composite_from_object() {
+    let column = test_column(ColumnType::Unknown(String::from("address")));
+    let value = coerce(
+        &column,
+        Value::Json(serde_json::json!({"street": "1 Market Street"})),
+    )
+    .unwrap();
+
+    assert_eq!(
+        "jsonb_populate_record(NULL::address, $2)",
+        placeholder(&column, &value, 2)
+    );
+}
+
+#[test]
+fn coerce_geojson() {
+    let column = test_column(ColumnType::Unknown(String::from("geometry")));
+    let value = coerce(
+        &column,
+        Value::Json(serde_json::json!({"type": "Point", "coordinates": [4.89, 52.37]})),
+    )
+    .unwrap();
+
+    assert_eq!(
+        "ST_GeomFromGeoJSON(($1::jsonb)::text)",
+        placeholder(&column, &value, 1)
+    );
+}
+
+#[test]
+fn coerce_range_object() {
+    let column = test_column(ColumnType::TsTzRange);
+    let value = coerce(
+        &column,
+        Value::Json(serde_json::json!({"lower": "2020-01-01T10:00:00Z", "upper": null})),
+    )
+    .unwrap();
+
+    assert_eq!(
+        value,
+        sea_query::Value::String(Some(Box::new("[\"2020-01-01T10:00:00Z\",)".to_string())))
+    );
+    assert_eq!("$1::text::tstzrange", placeholder(&column, &value, 1));
+    assert!(coerce(&column, Value::Json(serde_json::json!({"bounds": "<>"}))).is_err());
+}
@@ -1,6 +1,7 @@
 use crate::Value;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
 use serde::de::Error;
-use serde::de::{self, Unexpected, Visitor};
+use serde::de::{self, MapAccess, SeqAccess, Unexpected, Visitor};
 use serde::{Deserialize, Deserializer};
 use std::fmt;
 
@@ -57,7 +58,8 @@ impl<'de> Deserialize<'de> for Value {
             where
                 E: de::Error,
             {
-                Ok(Value::parse_str(value))
+                // strings are coerced later on, using the type of the column they are written to
+                Ok(Value::String(value.to_owned()))
             }
 
             fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
@@ -66,6 +68,23 @@ impl<'de> Deserialize<'de> for Value {
             {
                 self.visit_str(&value)
             }
+
+            // objects and arrays are kept as json, for json and composite columns
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let json = serde_json::Value::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(Value::Json(json))
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let json = serde_json::Value::deserialize(SeqAccessDeserializer::new(seq))?;
+                Ok(Value::Json(json))
+            }
         }
 
         deserializer.deserialize_any(ValueVisitor)
@@ -7,6 +7,9 @@ use std::{process::Stdio, sync::Arc};
 use tokio::{sync::Notify, task::JoinHandle};
 
 const SETUP_SQL: &str = include_str!("setup.sql");
+const JWT_SECRET: &str = "integration-test-secret";
+const API_KEY: &str = "integration-test-key";
+const CORS_ORIGIN: &str = "http://localhost:3000";
 
 struct SetupState {
     client: Client,
@@ -58,6 +61,12 @@ async fn setup() -> anyhow::Result<SetupState> {
 
         let mut child = cmd
             .command()
+            .env("RESTQL_CONFIG", "tests/restql.toml")
+            .env("RESTQL_JWT_SECRET", JWT_SECRET)
+            .env("RESTQL_ANONYMOUS_ROLE", "postgres")
+            .env("RESTQL_API_KEYS_TABLE", "restql.api_keys")
+            .env("RESTQL_CORS_ALLOWED_ORIGINS", CORS_ORIGIN)
+            .env("RESTQL_POLICY_FILE", "tests/policies.toml")
             .stdout(Stdio::null())
             // .stdout(Stdio::inherit())
             .spawn()
@@ -89,6 +98,51 @@ async fn inner(args: Arguments) -> anyhow::Result<Conclusion> {
         // Trial::test("insert_data", || trialing(insert_data())),
         // Trial::test("get string id", || trialing(get_string_id())),
         Trial::test("select on filter", || trialing(select_on_filter())),
+        Trial::test("filter coerced to column type", || {
+            trialing(filter_coerced_to_column_type())
+        }),
+        Trial::test("bytea as base64 and raw download", || {
+            trialing(bytea_base64_and_raw())
+        }),
+        Trial::test("enum, domain and composite types", || {
+            trialing(user_defined_types())
+        }),
+        Trial::test("geometry as geojson", || trialing(geometry_geojson())),
+        Trial::test("range types", || trialing(range_types())),
+        Trial::test("explicit select of converted columns", || {
+            trialing(explicit_select_converted_columns())
+        }),
+        Trial::test("errors as json with status", || {
+            trialing(errors_as_json_with_status())
+        }),
+        Trial::test("prometheus metrics", || trialing(prometheus_metrics())),
+        Trial::test(
+            "request id propagated",
+            || trialing(request_id_propagated()),
+        ),
+        Trial::test("health and version", || trialing(health_and_version())),
+        Trial::test("openapi document", || trialing(openapi_document())),
+        Trial::test(
+            "json schema per table",
+            || trialing(json_schema_per_table()),
+        ),
+        Trial::test("jwt role switching", || trialing(jwt_role_switching())),
+        Trial::test("row level security on claims", || {
+            trialing(row_level_security_on_claims())
+        }),
+        Trial::test("hashed column not echoed", || {
+            trialing(hashed_column_not_echoed())
+        }),
+        Trial::test("hidden columns refused", || {
+            trialing(hidden_columns_refused())
+        }),
+        Trial::test("api key from table", || trialing(api_key_from_table())),
+        Trial::test("cors preflight", || trialing(cors_preflight())),
+        Trial::test("request size limits", || trialing(request_size_limits())),
+        Trial::test("bulk insert", || trialing(bulk_insert())),
+        Trial::test("query cancelled on disconnect", || {
+            trialing(query_cancelled_on_disconnect())
+        }),
     ];
 
     Ok(libtest_mimic::run(&args, tests))
@@ -168,3 +222,675 @@ async fn select_on_filter() {
     assert_eq!(data[0]["mytitle"], "My First SQL book");
     assert!(data[0].get("id").is_none());
 }
+
+async fn filter_coerced_to_column_type() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/books?select=id,title&id=eq.2")
+        .send()
+        .await
+        .unwrap();
+
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+
+    assert!(data.len() == 1);
+    assert_eq!(data[0]["title"], "My Second SQL book");
+
+    let response = client
+        .get("http://localhost:9503/books?id=eq.abc")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["message"], "column id expects integer, got 'abc'");
+}
+
+async fn bytea_base64_and_raw() {
+    let data = serde_json::json!({"email": "bytea@example.com", "username": "bytea", "password": "bytea", "created_on": "2020-04-12T12:23:34", "avatar": "AAEC/v8="});
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:9503/accounts")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Map<String, serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(data["avatar"], "AAEC/v8=");
+
+    let id = data["id"].as_str().unwrap();
+    let response = client
+        .get(format!("http://localhost:9503/accounts/{id}?column=avatar"))
+        .header("Accept", "application/octet-stream")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/octet-stream"
+    );
+    assert_eq!(
+        response.bytes().await.unwrap().as_ref(),
+        &[0, 1, 2, 254, 255]
+    );
+}
+
+async fn user_defined_types() {
+    let data = serde_json::json!({"status": "paid", "amount": 5, "address": {"street": "1 Market Street", "city": "San Francisco"}});
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:9503/orders")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Map<String, serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(data["status"], "paid");
+    assert_eq!(data["amount"], 5);
+    assert_eq!(data["address"]["city"], "San Francisco");
+
+    let data = serde_json::json!({"status": "lost", "amount": 5});
+    let response = client
+        .post("http://localhost:9503/orders")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        error["message"],
+        "column status expects one of 'pending', 'paid', 'shipped', got 'lost'"
+    );
+}
+
+async fn geometry_geojson() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/locations?position=bbox.4.7,52.2,5.1,52.5")
+        .send()
+        .await
+        .unwrap();
+
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+
+    assert!(data.len() == 1);
+    assert_eq!(data[0]["name"], "Amsterdam");
+    assert_eq!(data[0]["position"]["type"], "Point");
+
+    let data = serde_json::json!({"name": "Utrecht", "position": {"type": "Point", "coordinates": [5.12, 52.09]}});
+    let response = client
+        .post("http://localhost:9503/locations")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Map<String, serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(data["position"]["coordinates"][0], 5.12);
+}
+
+async fn range_types() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/bookings?during=ov.[2020-01-01T11:00:00Z,2020-01-01T13:00:00Z)")
+        .send()
+        .await
+        .unwrap();
+
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+
+    assert!(data.len() == 1);
+    assert_eq!(data[0]["room"], "Kitchen");
+    assert_eq!(data[0]["during"]["bounds"], "[)");
+
+    let data = serde_json::json!({"room": "Garden", "during": {"lower": "2020-01-02T10:00:00Z", "upper": "2020-01-02T12:00:00Z", "bounds": "[]"}});
+    let response = client
+        .post("http://localhost:9503/bookings")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Map<String, serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(data["during"]["bounds"], "[]");
+}
+
+async fn explicit_select_converted_columns() {
+    let client = reqwest::Client::new();
+
+    let data = serde_json::json!({"amount": 7, "address": {"street": "2 Dam Square", "city": "Amsterdam"}});
+    let response = client
+        .post("http://localhost:9503/orders")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+    let order: serde_json::Map<String, serde_json::Value> = response.json().await.unwrap();
+
+    let response = client
+        .get(format!(
+            "http://localhost:9503/orders?select=id,shipping:address&id=eq.{}",
+            order["id"]
+        ))
+        .send()
+        .await
+        .unwrap();
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+    assert_eq!(data[0]["shipping"]["city"], "Amsterdam");
+
+    let response = client
+        .get("http://localhost:9503/locations?select=name,position&name=eq.Amsterdam")
+        .send()
+        .await
+        .unwrap();
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+    assert_eq!(data[0]["position"]["type"], "Point");
+
+    let response = client
+        .get("http://localhost:9503/bookings?select=id,during&room=eq.Kitchen")
+        .send()
+        .await
+        .unwrap();
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+    assert_eq!(data[0]["during"]["bounds"], "[)");
+
+    let response = client
+        .get("http://localhost:9503/bookings?select=id,starts")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+async fn errors_as_json_with_status() {
+    let data = serde_json::json!({"id": "ID-12345", "description": "duplicate"});
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:9503/items")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["code"], "23505");
+    assert_eq!(error["details"], "Key (id)=(ID-12345) already exists.");
+
+    let response = client
+        .get("http://localhost:9503/does_not_exist")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(error["message"], "table does_not_exist does not exist");
+}
+
+async fn prometheus_metrics() {
+    let client = reqwest::Client::new();
+    client
+        .get("http://localhost:9503/books")
+        .send()
+        .await
+        .unwrap();
+
+    let response = client
+        .get("http://localhost:9503/metrics")
+        .send()
+        .await
+        .unwrap();
+
+    let text = response.text().await.unwrap();
+    assert!(text.contains("restql_http_requests_total"));
+    assert!(text.contains("table=\"books\""));
+    assert!(text.contains("restql_db_query_duration_seconds_bucket"));
+    assert!(text.contains("restql_pool_connections_in_use"));
+}
+
+async fn request_id_propagated() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/books")
+        .header("X-Request-Id", "my-request-id")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["x-request-id"], "my-request-id");
+
+    let response = client
+        .get("http://localhost:9503/books")
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.headers().contains_key("x-request-id"));
+}
+
+async fn health_and_version() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/health/live")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://localhost:9503/health/ready")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://localhost:9503/version")
+        .send()
+        .await
+        .unwrap();
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["version"], env!("CARGO_PKG_VERSION"));
+    assert!(data["postgres"].as_str().unwrap().starts_with("15"));
+}
+
+async fn openapi_document() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/")
+        .header("Accept", "application/openapi+json")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/openapi+json"
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["openapi"], "3.1.0");
+    assert!(data["paths"]["/books"]["get"].is_object());
+    assert!(data["paths"]["/books/{id}"]["patch"].is_object());
+
+    let accounts = &data["components"]["schemas"]["accounts"];
+    assert_eq!(accounts["properties"]["username"]["maxLength"], 50);
+    assert_eq!(
+        accounts["properties"]["last_login"]["type"],
+        serde_json::json!(["string", "null"])
+    );
+    assert_eq!(
+        data["components"]["schemas"]["orders"]["properties"]["status"]["enum"],
+        serde_json::json!(["pending", "paid", "shipped"])
+    );
+    assert_eq!(
+        data["components"]["schemas"]["orders"]["properties"]["status"]["default"],
+        "pending"
+    );
+}
+
+async fn json_schema_per_table() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/_schema/accounts")
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        data["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+    assert_eq!(data["properties"]["username"]["maxLength"], 50);
+    assert!(data["required"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("username")));
+
+    let response = client
+        .get("http://localhost:9503/_schema/reviews?operation=update")
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["properties"]["rating"]["minimum"], 1);
+    assert_eq!(data["properties"]["rating"]["maximum"], 5);
+    assert!(data.get("required").is_none());
+
+    let data = serde_json::json!({"email": "long@example.com", "username": "x".repeat(51), "password": "long", "created_on": "2020-04-12T12:23:34"});
+    let response = client
+        .post("http://localhost:9503/accounts")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert!(error["message"]
+        .as_str()
+        .unwrap()
+        .contains("is longer than 50 characters"));
+}
+
+async fn jwt_role_switching() {
+    let claims = serde_json::json!({"role": "restql_reader", "exp": 4102444800u64});
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .unwrap();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/books")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let data = serde_json::json!({"title": "Forbidden", "author": "Nobody", "published_date": "2020-04-12T12:23:34"});
+    let response = client
+        .post("http://localhost:9503/books")
+        .bearer_auth(&token)
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .get("http://localhost:9503/books")
+        .bearer_auth("not-a-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+}
+
+async fn row_level_security_on_claims() {
+    let claims = serde_json::json!({"role": "restql_reader", "sub": "alice", "exp": 4102444800u64});
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .unwrap();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/notes")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+    assert_eq!(data.len(), 1);
+    assert_eq!(data[0]["body"], "note of alice");
+}
+
+async fn hashed_column_not_echoed() {
+    let data = serde_json::json!({"email": "hashed@example.com", "username": "hashed", "password": "correct horse", "created_on": "2020-04-12T12:23:34"});
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:9503/accounts")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+
+    let data: serde_json::Map<String, serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(data["username"], "hashed");
+    assert!(!data.contains_key("password"));
+
+    let response = client
+        .get(format!(
+            "http://localhost:9503/accounts?select=password&id=eq.{}",
+            data["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+    assert!(data[0]["password"]
+        .as_str()
+        .unwrap()
+        .starts_with("$argon2id$"));
+}
+
+async fn hidden_columns_refused() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/members")
+        .send()
+        .await
+        .unwrap();
+
+    let data: Vec<serde_json::Map<String, serde_json::Value>> = response.json().await.unwrap();
+    assert_eq!(data[0]["name"], "alice");
+    assert!(!data[0].contains_key("pin"));
+
+    for select in ["id,pin", "*", "id,members(pin)", "id,members(*)"] {
+        let response = client
+            .get(format!("http://localhost:9503/members?select={select}"))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_client_error(), "{select}");
+        let body = response.text().await.unwrap();
+        assert!(!body.contains("1234"), "{select}");
+    }
+}
+
+async fn api_key_from_table() {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("http://localhost:9503/books")
+        .header("X-Api-Key", API_KEY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // restql_reader may select notes, but the key is limited to books
+    let response = client
+        .get("http://localhost:9503/notes")
+        .header("X-Api-Key", API_KEY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .get("http://localhost:9503/books")
+        .header("X-Api-Key", "not-a-key")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+async fn cors_preflight() {
+    let client = reqwest::Client::new();
+    let response = client
+        .request(reqwest::Method::OPTIONS, "http://localhost:9503/books")
+        .header("Origin", CORS_ORIGIN)
+        .header("Access-Control-Request-Method", "POST")
+        .header(
+            "Access-Control-Request-Headers",
+            "authorization, content-type",
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers["access-control-allow-origin"], CORS_ORIGIN);
+    assert!(headers["access-control-allow-methods"]
+        .to_str()
+        .unwrap()
+        .contains("POST"));
+
+    let response = client
+        .get("http://localhost:9503/books")
+        .header("Origin", CORS_ORIGIN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        CORS_ORIGIN
+    );
+    assert!(response.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap()
+        .contains("content-range"));
+
+    // other origins get no cors headers, so the browser refuses the response
+    let response = client
+        .get("http://localhost:9503/books")
+        .header("Origin", "http://evil.example.com")
+        .send()
+        .await
+        .unwrap();
+    assert!(response
+        .headers()
+        .get("access-control-allow-origin")
+        .is_none());
+}
+
+async fn request_size_limits() {
+    let client = reqwest::Client::new();
+    let data = serde_json::json!({"title": "x".repeat(3 * 1024 * 1024), "author": "Nobody"});
+    let response = client
+        .post("http://localhost:9503/books")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+    let response = client
+        .get("http://localhost:9503/books?select=id,a(b(c(d(e))))")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        error["message"],
+        "select is nested 4 levels deep, at most 3 are allowed"
+    );
+}
+
+async fn bulk_insert() {
+    let client = reqwest::Client::new();
+    let data = serde_json::json!([
+        {"status": "paid", "amount": 101},
+        {"status": "pending", "amount": 102},
+    ]);
+    let response = client
+        .post("http://localhost:9503/orders")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let rows: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["amount"], 101);
+    assert_eq!(rows[1]["status"], "pending");
+
+    // one invalid row rolls back the others
+    let data = serde_json::json!([
+        {"status": "paid", "amount": 103},
+        {"status": "lost", "amount": 104},
+    ]);
+    let response = client
+        .post("http://localhost:9503/orders")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let response = client
+        .get("http://localhost:9503/orders?amount=eq.103")
+        .send()
+        .await
+        .unwrap();
+    let rows: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert!(rows.is_empty());
+
+    let data = serde_json::Value::Array(vec![
+        serde_json::json!({"status": "paid", "amount": 1});
+        1001
+    ]);
+    let response = client
+        .post("http://localhost:9503/orders")
+        .json(&data)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+async fn query_cancelled_on_disconnect() {
+    let (db, connection) = tokio_postgres::connect(
+        "host=localhost port=5432 user=postgres password=example dbname=postgres",
+        NoTls,
+    )
+    .await
+    .unwrap();
+    tokio::spawn(connection);
+    let running = "SELECT count(*) FROM pg_stat_activity \
+        WHERE state = 'active' AND query LIKE 'INSERT INTO slow_inserts%'";
+
+    let request = tokio::spawn(async {
+        reqwest::Client::new()
+            .post("http://localhost:9503/slow_inserts")
+            .json(&serde_json::json!({"body": "never"}))
+            .send()
+            .await
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let active: i64 = db.query_one(running, &[]).await.unwrap().get(0);
+    assert_eq!(active, 1);
+
+    // the client gives up long before the trigger is done sleeping
+    request.abort();
+    let mut active = 1;
+    for _ in 0..50 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        active = db.query_one(running, &[]).await.unwrap().get(0);
+        if active == 0 {
+            break;
+        }
+    }
+    assert_eq!(
+        active, 0,
+        "the insert kept running after the client went away"
+    );
+    let rows: i64 = db
+        .query_one("SELECT count(*) FROM slow_inserts", &[])
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(rows, 0);
+}
@@ -1,3 +1,5 @@
+CREATE EXTENSION IF NOT EXISTS postgis;
+
 DROP TABLE IF EXISTS accounts;
 DROP TABLE IF EXISTS items;
 DROP TABLE IF EXISTS users_books;
@@ -5,15 +7,28 @@ DROP TABLE IF EXISTS reviews;
 DROP TABLE IF EXISTS addresses;
 DROP TABLE IF EXISTS users;
 DROP TABLE IF EXISTS books;
+DROP TABLE IF EXISTS orders;
+DROP TABLE IF EXISTS locations;
+DROP TABLE IF EXISTS bookings;
+DROP TABLE IF EXISTS notes;
+DROP TABLE IF EXISTS slow_inserts;
+DROP TABLE IF EXISTS members;
+DROP FUNCTION IF EXISTS sleep_before_insert;
+DROP SCHEMA IF EXISTS restql CASCADE;
+DROP TYPE IF EXISTS order_status;
+DROP DOMAIN IF EXISTS positive_amount;
+DROP TYPE IF EXISTS shipping_address;
+DROP ROLE IF EXISTS restql_reader;
 
 
 CREATE TABLE accounts (
         id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
         username VARCHAR ( 50 ) UNIQUE NOT NULL,
-        password VARCHAR ( 50 ) NOT NULL,
+        password VARCHAR ( 100 ) NOT NULL, -- an argon2 hash, see tests/restql.toml
         email VARCHAR ( 255 ) UNIQUE NOT NULL,
         created_on TIMESTAMP NOT NULL,
         last_login TIMESTAMP,
+        avatar BYTEA,
         created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
 );
 
@@ -23,6 +38,31 @@ CREATE TABLE items (
         created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
 );
 
+CREATE TYPE order_status AS ENUM ('pending', 'paid', 'shipped');
+CREATE DOMAIN positive_amount AS integer CHECK (VALUE > 0);
+CREATE TYPE shipping_address AS (
+        street VARCHAR (30),
+        city VARCHAR (30)
+);
+
+CREATE TABLE orders (
+        id serial PRIMARY KEY,
+        status order_status NOT NULL DEFAULT 'pending',
+        amount positive_amount NOT NULL,
+        address shipping_address
+);
+
+CREATE TABLE locations (
+        id serial PRIMARY KEY,
+        name VARCHAR (100) NOT NULL,
+        position geometry(Point, 4326) NOT NULL
+);
+
+CREATE TABLE bookings (
+        id serial PRIMARY KEY,
+        room VARCHAR (30) NOT NULL,
+        during tstzrange NOT NULL
+);
 
 -- from https://launchschool.com/books/sql_first_edition/read/multi_tables
 
@@ -66,7 +106,7 @@ CREATE TABLE reviews (
   book_id int NOT NULL,
   user_id int NOT NULL,
   review_content VARCHAR(255),
-  rating int,
+  rating int CHECK (rating BETWEEN 1 AND 5),
   published_date timestamp DEFAULT CURRENT_TIMESTAMP,
   PRIMARY KEY (id),
   FOREIGN KEY (book_id) REFERENCES books(id) ON DELETE CASCADE,
@@ -137,3 +177,62 @@ INSERT INTO users (id, username) VALUES (3, 'Alice Munro');
 -- A Third book
 INSERT INTO books (id, title, author, published_date)
 VALUES(3, 'My Third SQL book','Cary Flint', NOW());
+
+INSERT INTO locations (name, position)
+VALUES ('Amsterdam', ST_SetSRID(ST_MakePoint(4.89, 52.37), 4326));
+
+INSERT INTO locations (name, position)
+VALUES ('San Francisco', ST_SetSRID(ST_MakePoint(-122.42, 37.77), 4326));
+
+INSERT INTO bookings (room, during)
+VALUES ('Kitchen', '[2020-01-01 10:00:00+00,2020-01-01 12:00:00+00)');
+
+CREATE ROLE restql_reader NOLOGIN;
+GRANT SELECT ON books TO restql_reader;
+
+-- row level security on the claims published per request
+CREATE TABLE notes (
+        id serial PRIMARY KEY,
+        owner TEXT NOT NULL,
+        body TEXT NOT NULL
+);
+ALTER TABLE notes ENABLE ROW LEVEL SECURITY;
+CREATE POLICY notes_owner ON notes
+        USING (owner = current_setting('request.jwt.claims', true)::json->>'sub');
+GRANT SELECT ON notes TO restql_reader;
+INSERT INTO notes (owner, body) VALUES ('alice', 'note of alice'), ('bob', 'note of bob');
+
+-- hashed api keys, in a schema that is not exposed
+CREATE SCHEMA restql;
+CREATE TABLE restql.api_keys (
+        name TEXT PRIMARY KEY,
+        key_sha256 TEXT NOT NULL UNIQUE,
+        role TEXT NOT NULL,
+        tables TEXT[],
+        rate_limit_requests INTEGER,
+        rate_limit_per_secs INTEGER
+);
+INSERT INTO restql.api_keys (name, key_sha256, role, tables)
+VALUES ('reader', encode(sha256('integration-test-key'), 'hex'), 'restql_reader', '{books}');
+
+-- inserts that run for a minute, to cancel when the client disconnects
+CREATE TABLE slow_inserts (
+        id serial PRIMARY KEY,
+        body TEXT NOT NULL
+);
+CREATE FUNCTION sleep_before_insert() RETURNS trigger AS $$
+BEGIN
+        PERFORM pg_sleep(60);
+        RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER slow_inserts_sleep BEFORE INSERT ON slow_inserts
+        FOR EACH ROW EXECUTE FUNCTION sleep_before_insert();
+
+-- pin is hidden by tests/policies.toml
+CREATE TABLE members (
+        id serial PRIMARY KEY,
+        name TEXT NOT NULL,
+        pin TEXT NOT NULL
+);
+INSERT INTO members (name, pin) VALUES ('alice', '1234');